@@ -0,0 +1,426 @@
+use std::{
+    collections::HashMap,
+    error, fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{common, seek};
+
+// The archive is one raw deflate stream, in it is:
+//
+//   i32 entry count
+//   per entry: u8 name length, name, u8 type
+//   per entry: i32 payload length, payload
+//
+// so everything has to be read front to back.
+
+// names have a u8 length, counts and payload lengths are i32
+pub const MAX_NAME_LEN: usize = u8::MAX as usize;
+pub const MAX_LEN: u64 = i32::MAX as u64;
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub type_: u8,
+}
+
+// index into ArchiveReader::entries and the data
+pub type Payload = (usize, Vec<u8>);
+
+// an entry together with its data
+pub type Loaded = (Entry, Vec<u8>);
+
+pub struct ArchiveReader<R: Read> {
+    rdr: DeflateDecoder<R>,
+    pub entries: Vec<Entry>,
+    next: usize,
+}
+
+impl ArchiveReader<fs::File> {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn error::Error>> {
+        Self::new(fs::File::open(path)?)
+    }
+}
+
+impl<R: Read> ArchiveReader<R> {
+    // reads the header, payloads are read with next_entry
+    pub fn new(r: R) -> Result<Self, Box<dyn error::Error>> {
+        let mut rdr = DeflateDecoder::new(r);
+
+        let mut entries = vec![];
+        let entries_n = rdr.read_i32::<LittleEndian>()?;
+        for _ in 0..entries_n {
+            let len = rdr.read_u8()?;
+            let name = common::read_string(&mut rdr, len)?;
+            let type_ = rdr.read_u8()?;
+            entries.push(Entry { name, type_ });
+        }
+
+        Ok(ArchiveReader {
+            rdr,
+            entries,
+            next: 0,
+        })
+    }
+
+    // returns the index of the entry and its payload, None after the last one
+    pub fn next_entry(&mut self) -> Result<Option<Payload>, Box<dyn error::Error>> {
+        if self.next >= self.entries.len() {
+            return Ok(None);
+        }
+        let i = self.next;
+        self.next += 1;
+        Ok(Some((i, self.read_payload()?)))
+    }
+
+    fn read_payload(&mut self) -> Result<Vec<u8>, Box<dyn error::Error>> {
+        let len = self.rdr.read_i32::<LittleEndian>()?;
+        if len < 0 {
+            return Err(format!("Entry {} has a length of {}", self.next - 1, len).into());
+        }
+        // a broken length shouldn't get to allocate gigabytes before failing
+        let mut buffer = vec![];
+        (&mut self.rdr).take(len as u64).read_to_end(&mut buffer)?;
+        if buffer.len() != len as usize {
+            return Err(format!(
+                "Archive ends inside entry {}, run verify to see what can be saved",
+                self.next - 1
+            )
+            .into());
+        }
+        Ok(buffer)
+    }
+}
+
+// the other way around, the header goes out right away and then one payload per
+// entry in the same order
+pub struct ArchiveWriter<W: Write> {
+    comp: DeflateEncoder<W>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(w: W, entries: &[Entry]) -> Result<Self, Box<dyn error::Error>> {
+        Self::with_level(w, entries, Compression::best())
+    }
+
+    pub fn with_level(
+        w: W,
+        entries: &[Entry],
+        level: Compression,
+    ) -> Result<Self, Box<dyn error::Error>> {
+        if entries.len() as u64 > MAX_LEN {
+            return Err(format!("{} entries don't fit in an archive", entries.len()).into());
+        }
+        let mut comp = DeflateEncoder::new(w, level);
+        comp.write_i32::<LittleEndian>(entries.len() as i32)?;
+        for x in entries {
+            if let Some(e) = entry_problems(&x.name, 0).first() {
+                return Err(format!("\"{}\": {}", x.name, e).into());
+            }
+            let name = common::encode_name(&x.name);
+            comp.write_u8(name.len() as u8)?;
+            comp.write_all(&name)?;
+            comp.write_u8(x.type_)?;
+        }
+        Ok(ArchiveWriter { comp })
+    }
+
+    pub fn add(&mut self, data: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        if data.len() as u64 > MAX_LEN {
+            return Err(format!("Entry of {} bytes doesn't fit in an archive", data.len()).into());
+        }
+        self.comp.write_i32::<LittleEndian>(data.len() as i32)?;
+        self.comp.write_all(data)?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<W, Box<dyn error::Error>> {
+        Ok(self.comp.finish()?)
+    }
+}
+
+// a whole archive in memory
+pub fn write_all(entries: &[Loaded]) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    let headers: Vec<Entry> = entries.iter().map(|x| x.0.clone()).collect();
+    let mut w = ArchiveWriter::new(vec![], &headers)?;
+    for x in entries {
+        w.add(&x.1)?;
+    }
+    w.finish()
+}
+
+// why an entry with this name and payload length can't go in the header
+pub fn entry_problems(name: &str, len: u64) -> Vec<String> {
+    let mut problems = vec![];
+    let name_len = common::encode_name(name).len();
+    if name_len > MAX_NAME_LEN {
+        problems.push(format!(
+            "name is {} bytes, the archive allows {}",
+            name_len, MAX_NAME_LEN
+        ));
+    }
+    if len > MAX_LEN {
+        problems.push(format!(
+            "data is {} bytes, the archive allows {}",
+            len, MAX_LEN
+        ));
+    }
+    problems
+}
+
+// the game expects textures to be loaded before anything with geometry, pack sorts
+// by type id for that. new entries go behind the last one with the same or a
+// higher type id so that still holds
+pub fn insert_position<'a>(entries: impl Iterator<Item = &'a Entry>, type_: u8) -> usize {
+    entries
+        .enumerate()
+        .filter(|(_, x)| x.type_ >= type_)
+        .last()
+        .map(|(i, _)| i + 1)
+        .unwrap_or(0)
+}
+
+// textures that come after a box, obj or map
+pub fn order_problems(entries: &[Entry]) -> Vec<String> {
+    let first = match entries.iter().position(|x| matches!(x.type_, 0..=2)) {
+        Some(v) => v,
+        None => return vec![],
+    };
+    entries[first..]
+        .iter()
+        .filter(|x| x.type_ == 10)
+        .map(|x| {
+            format!(
+                "texture \"{}\" comes after \"{}\", the game won't find it",
+                x.name, entries[first].name
+            )
+        })
+        .collect()
+}
+
+// hash of everything inside the deflate stream, so it doesn't matter how an archive
+// was compressed as long as the same entries are in it in the same order
+pub fn content_hash(path: &Path) -> Result<String, Box<dyn error::Error>> {
+    let mut rdr = DeflateDecoder::new(fs::File::open(path)?);
+    Ok(common::hash_hex_reader(&mut rdr)?)
+}
+
+// everything inside the deflate stream as is, and back
+pub fn read_stream(path: &Path) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    let mut data = vec![];
+    DeflateDecoder::new(fs::File::open(path)?).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    let mut comp = DeflateEncoder::new(vec![], Compression::best());
+    comp.write_all(data)?;
+    Ok(comp.finish()?)
+}
+
+// every entry with its payload, in archive order
+pub fn read_all(path: &Path) -> Result<Vec<Loaded>, Box<dyn error::Error>> {
+    let mut rdr = ArchiveReader::open(path)?;
+    let mut all = vec![];
+    while let Some((i, data)) = rdr.next_entry()? {
+        all.push((rdr.entries[i].clone(), data));
+    }
+    Ok(all)
+}
+
+// reads a single entry. goes through the index next to the archive if there is
+// one, otherwise through everything before the entry
+pub fn read_entry(path: &Path, name: &str) -> Result<(Entry, Vec<u8>), Box<dyn error::Error>> {
+    if let Some(v) = seek::read_entry(path, name)? {
+        return Ok(v);
+    }
+    let mut rdr = ArchiveReader::open(path)?;
+    while let Some((i, data)) = rdr.next_entry()? {
+        if rdr.entries[i].name == name {
+            return Ok((rdr.entries[i].clone(), data));
+        }
+    }
+    Err(format!("{:?} has no entry \"{}\"", path, name).into())
+}
+
+// splits "common.dat:models/man.box" into the archive and the entry name.
+// only counts if the part before the colon is an existing .dat file, so windows
+// drive letters and plain files with colons are left alone
+pub fn split_entry_path(input: &str) -> Option<(&Path, &str)> {
+    let mut search = input.len();
+    while let Some(i) = input[..search].rfind(':') {
+        let (archive, entry) = (&input[..i], &input[i + 1..]);
+        let archive_path = Path::new(archive);
+        let is_dat = archive_path
+            .extension()
+            .map(|x| x.eq_ignore_ascii_case("dat"))
+            .unwrap_or(false);
+        if is_dat && archive_path.is_file() && !entry.is_empty() {
+            return Some((archive_path, entry));
+        }
+        search = i;
+    }
+    None
+}
+
+// Entry names come straight from whoever made the archive, so before writing them
+// anywhere they're turned into a path that can't leave the output folder.
+
+// names windows won't create files with, with or without an extension
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// relative path for an entry name, / and \ are both separators and .. is resolved.
+// errors if it's absolute, empty or would end up outside the folder
+pub fn safe_path(name: &str) -> Result<PathBuf, String> {
+    if name.contains('\0') {
+        return Err(String::from("contains a NUL character"));
+    }
+    if name.starts_with(['/', '\\']) {
+        return Err(String::from("is an absolute path"));
+    }
+    let mut parts: Vec<&str> = vec![];
+    for (i, x) in name.split(['/', '\\']).enumerate() {
+        match x {
+            "" | "." => (),
+            ".." => {
+                if parts.pop().is_none() {
+                    return Err(String::from("points outside the output folder"));
+                }
+            }
+            // drive letters
+            _ if i == 0 && x.as_bytes().get(1) == Some(&b':') => {
+                return Err(String::from("is an absolute path"))
+            }
+            _ => parts.push(x),
+        }
+    }
+    if parts.is_empty() {
+        return Err(String::from("is not a file name"));
+    }
+    Ok(parts.iter().collect())
+}
+
+// things that keep a name from being unpacked the same on every system, mostly windows
+pub fn portability_problems(name: &str) -> Vec<String> {
+    let mut problems = vec![];
+    for x in name.split(['/', '\\']).filter(|x| !x.is_empty()) {
+        let stem = x.split('.').next().unwrap().trim_end();
+        if RESERVED.iter().any(|v| v.eq_ignore_ascii_case(stem)) {
+            problems.push(format!("\"{}\" is a reserved name on windows", x));
+        }
+        if let Some(c) = x
+            .chars()
+            .find(|c| c.is_control() || "<>:\"|?*".contains(*c))
+        {
+            problems.push(format!("\"{}\" has {:?} which windows doesn't allow", x, c));
+        }
+        if x != "." && x != ".." && x.ends_with(['.', ' ']) {
+            problems.push(format!(
+                "\"{}\" ends with a dot or space, windows drops those",
+                x
+            ));
+        }
+    }
+    problems
+}
+
+pub struct NameCheck {
+    // where the entry goes relative to the output folder, Err if it must not be written
+    pub path: Result<PathBuf, String>,
+    // can be written but might not come out right
+    pub warnings: Vec<String>,
+}
+
+// checks all names of an archive together, so duplicates are caught too. the first
+// entry with a path wins, later ones would overwrite it
+pub fn check_names(entries: &[Entry]) -> Vec<NameCheck> {
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+    let mut seen_lower: HashMap<String, usize> = HashMap::new();
+    let mut checks = vec![];
+    for (i, x) in entries.iter().enumerate() {
+        let mut warnings = vec![];
+        let path = safe_path(&x.name).and_then(|path| {
+            warnings = portability_problems(&x.name);
+            if let Some(j) = seen.get(&path) {
+                return Err(format!("is the same file as \"{}\"", entries[*j].name));
+            }
+            // fine on linux, but on windows and macos one overwrites the other
+            let lower = path.to_string_lossy().to_lowercase();
+            if let Some(j) = seen_lower.get(&lower) {
+                warnings.push(format!(
+                    "only differs in case from \"{}\"",
+                    entries[*j].name
+                ));
+            }
+            seen.insert(path.clone(), i);
+            seen_lower.entry(lower).or_insert(i);
+            Ok(path)
+        });
+        checks.push(NameCheck { path, warnings });
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(name: &str) -> PathBuf {
+        safe_path(name).unwrap()
+    }
+
+    #[test]
+    fn plain_names() {
+        assert_eq!(ok("models/man.box"), Path::new("models").join("man.box"));
+        assert_eq!(ok("models\\man.box"), Path::new("models").join("man.box"));
+        assert_eq!(ok("man.box"), PathBuf::from("man.box"));
+    }
+
+    #[test]
+    fn parent_dirs() {
+        assert_eq!(
+            ok("models/../textures/a.png"),
+            Path::new("textures").join("a.png")
+        );
+        assert!(safe_path("../a.png").is_err());
+        assert!(safe_path("models/../../a.png").is_err());
+        assert!(safe_path("models\\..\\..\\a.png").is_err());
+        assert!(safe_path("models/..").is_err());
+    }
+
+    #[test]
+    fn absolute_paths() {
+        assert!(safe_path("/etc/passwd").is_err());
+        assert!(safe_path("\\windows\\a.png").is_err());
+        assert!(safe_path("C:\\windows\\a.png").is_err());
+        assert!(safe_path("c:/a.png").is_err());
+        assert!(safe_path("C:a.png").is_err());
+        assert!(safe_path("\\\\server\\share\\a.png").is_err());
+        assert!(safe_path("//server/share/a.png").is_err());
+    }
+
+    #[test]
+    fn empty_components() {
+        assert_eq!(ok("models//man.box"), Path::new("models").join("man.box"));
+        assert_eq!(
+            ok("./models/./man.box"),
+            Path::new("models").join("man.box")
+        );
+        assert_eq!(ok("models/"), PathBuf::from("models"));
+        assert!(safe_path("").is_err());
+        assert!(safe_path(".").is_err());
+        assert!(safe_path("./").is_err());
+    }
+
+    #[test]
+    fn nul() {
+        assert!(safe_path("man\0.box").is_err());
+    }
+}
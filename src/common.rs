@@ -1,9 +1,34 @@
-use std::{error, io::Read};
+use std::{
+    error,
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::OnceLock,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use encoding_rs::Encoding;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 
 // all formats seem to share a common version number
 // it's just "1" in big endian but read as little
 pub const FORMAT_VERSION: i32 = 16777216;
 
+// size of the static name fields (textures, models) used all over the formats
+pub const NAME_LEN: usize = 100;
+
+// only names that can't be stored at all, a name can take the whole field without
+// a NULL since read_fixed_string takes those and they have to come back the same
+pub fn fixed_string_problem(what: &str, s: &str, l: usize) -> Option<String> {
+    let len = encode_name(s).len();
+    if len <= l {
+        return None;
+    }
+    Some(format!(
+        "{} \"{}\" is {} bytes, the field fits {}",
+        what, s, len, l
+    ))
+}
+
 #[derive(Debug)]
 pub struct Tri {
     pub a: i32,
@@ -14,9 +39,337 @@ pub struct Tri {
     pub tc: i32,
 }
 
+// some text formats (toml) only know f64, so 1.1f32 ends up as 1.100000023841858.
+// going through the shortest f32 string keeps it readable and still gives the same bits back.
+// json has no NaN or infinity and neither keeps the bits of a NaN, so those are written
+// as their bits in a string, e.g. "0x7fc00000"
+pub fn serialize_f32<S: serde::Serializer>(v: &f32, s: S) -> Result<S::Ok, S::Error> {
+    if !v.is_finite() {
+        return s.serialize_str(&format!("{:#010x}", v.to_bits()));
+    }
+    s.serialize_f64(v.to_string().parse().unwrap())
+}
+
+pub fn deserialize_f32<'de, D: serde::Deserializer<'de>>(d: D) -> Result<f32, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Number(f64),
+        Bits(String),
+    }
+    match Value::deserialize(d)? {
+        Value::Number(v) => Ok(v as f32),
+        Value::Bits(v) => v
+            .strip_prefix("0x")
+            .and_then(|x| u32::from_str_radix(x, 16).ok())
+            .map(f32::from_bits)
+            .ok_or_else(|| {
+                serde::de::Error::custom(format!("\"{}\" isn't a number or the bits of one", v))
+            }),
+    }
+}
+
+// sha256 as lowercase hex, used wherever files are compared or recorded
+pub fn hash_hex(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+// same for everything a reader gives, without holding it all in memory
+pub fn hash_hex_reader<R: Read>(r: &mut R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(r, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+// a relative path as a URI reference (gltf buffers and images, links), everything
+// but the unreserved characters and the separators is percent-encoded (RFC 3986)
+pub fn uri_path(s: &str) -> String {
+    s.bytes()
+        .map(|x| match x {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                char::from(x).to_string()
+            }
+            _ => format!("%{:02X}", x),
+        })
+        .collect()
+}
+
+// Names in archives and models are just bytes, most are ascii but older files can
+// have korean names in a legacy encoding. They're decoded as UTF-8, then as the legacy
+// encoding if one was set, and if neither gives the exact bytes back the odd bytes
+// are written as %XX. encode_name turns every one of those back into the same bytes,
+// so pack and the writers restore the original.
+//
+// With a legacy encoding the same text could come from two different byte strings,
+// so plain text always means the legacy encoding and UTF-8 names that it would write
+// differently keep their non-ascii bytes escaped. That way a name alone is enough to
+// get the bytes back, whatever else was read before.
+static LEGACY_ENCODING: OnceLock<&'static Encoding> = OnceLock::new();
+
+// label as in the WHATWG encoding standard, e.g. "euc-kr" (which is really cp949).
+// windows code pages also work as "cpNNN"
+pub fn set_legacy_encoding(label: &str) -> Result<(), Box<dyn error::Error>> {
+    let windows = label
+        .to_lowercase()
+        .strip_prefix("cp")
+        .map(|x| format!("windows-{}", x));
+    let encoding = Encoding::for_label(windows.as_deref().unwrap_or(label).as_bytes())
+        .ok_or_else(|| format!("Unknown encoding \"{}\"", label))?;
+    // escapes and the rest of the name have to stay ascii
+    if !encoding.is_ascii_compatible() {
+        return Err(format!("Encoding \"{}\" can't be used for names", label).into());
+    }
+    LEGACY_ENCODING
+        .set(encoding)
+        .map_err(|_| "The legacy encoding can only be set once")?;
+    Ok(())
+}
+
+fn hex_at(s: &[u8], i: usize) -> Option<u8> {
+    let hex = std::str::from_utf8(s.get(i + 1..i + 3)?).ok()?;
+    if s[i] != b'%' || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+fn has_escapes(s: &str) -> bool {
+    (0..s.len()).any(|i| hex_at(s.as_bytes(), i).is_some())
+}
+
+fn encode_text(s: &str) -> Vec<u8> {
+    if let Some(v) = LEGACY_ENCODING.get() {
+        let (bytes, _, unmappable) = v.encode(s);
+        if !unmappable {
+            return bytes.into_owned();
+        }
+    }
+    s.as_bytes().to_vec()
+}
+
+pub fn encode_name(s: &str) -> Vec<u8> {
+    if !has_escapes(s) {
+        return encode_text(s);
+    }
+    let mut buf = vec![];
+    let mut text = String::new();
+    let mut i = 0;
+    while i < s.len() {
+        if let Some(v) = hex_at(s.as_bytes(), i) {
+            buf.extend(encode_text(&text));
+            text.clear();
+            buf.push(v);
+            i += 3;
+        } else {
+            let c = s[i..].chars().next().unwrap();
+            text.push(c);
+            i += c.len_utf8();
+        }
+    }
+    buf.extend(encode_text(&text));
+    buf
+}
+
+pub fn decode_name(buf: &[u8]) -> String {
+    // valid UTF-8 is never read as the legacy encoding, it would only look like it
+    let text = match std::str::from_utf8(buf) {
+        Ok(v) => Some(v.to_owned()),
+        Err(_) => LEGACY_ENCODING.get().and_then(|v| {
+            v.decode_without_bom_handling_and_without_replacement(buf)
+                .map(|x| x.into_owned())
+        }),
+    };
+    // only if it comes back the same, otherwise the escapes can do it
+    if let Some(v) = text {
+        if !has_escapes(&v) && encode_text(&v) == buf {
+            return v;
+        }
+    }
+    buf.iter()
+        .map(|x| match x {
+            b'%' | 0x80.. => format!("%{:02X}", x),
+            _ => char::from(*x).to_string(),
+        })
+        .collect()
+}
+
 pub fn read_string<R: Read>(r: &mut R, l: u8) -> Result<String, Box<dyn error::Error>> {
     let mut buf = vec![0u8; l as usize];
     r.read_exact(&mut buf)?;
-    let str = String::from_utf8(buf)?;
-    Ok(str)
+    Ok(decode_name(&buf))
+}
+
+// static size strings are NULL terminated, but whatever comes after the terminator
+// isn't always zeroed. it's returned as the second value (empty if it is just padding)
+// so writers can put back the exact same bytes.
+pub fn read_fixed_string<R: Read>(
+    r: &mut R,
+    l: usize,
+) -> Result<(String, Vec<u8>), Box<dyn error::Error>> {
+    let mut buf = vec![0u8; l];
+    r.read_exact(&mut buf)?;
+    let end = buf.iter().position(|x| *x == 0).unwrap_or(l);
+    let mut tail = buf.split_off(end);
+    if tail.iter().all(|x| *x == 0) {
+        tail.clear();
+    } else {
+        // drop the terminator and the zeros after the last used byte
+        let last = tail.iter().rposition(|x| *x != 0).unwrap();
+        tail.truncate(last + 1);
+        tail.remove(0);
+    }
+    Ok((decode_name(&buf), tail))
+}
+
+pub fn write_fixed_string<W: Write>(
+    w: &mut W,
+    s: &str,
+    tail: &[u8],
+    l: usize,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut buf = encode_name(s);
+    if buf.contains(&0) {
+        return Err(format!("String {:?} contains a NULL byte", s).into());
+    }
+    if !tail.is_empty() {
+        buf.push(0);
+        buf.extend_from_slice(tail);
+    }
+    if buf.len() > l {
+        return Err(format!("String {:?} does not fit in {} bytes", s, l).into());
+    }
+    buf.resize(l, 0);
+    w.write_all(&buf)?;
+    Ok(())
+}
+
+// the single file tables (animate.dat, carproperty.dat) look the same on the outside:
+// an optional version, a record count and then records that are all the same size.
+// the record size isn't stored anywhere, so it's whatever is left divided by the count.
+pub struct TableHeader {
+    pub version: Option<i32>,
+    pub count: i32,
+    pub record_size: u64,
+}
+
+pub fn read_table_header<R: Read + Seek>(
+    f: &mut R,
+    record_min: u64,
+) -> Result<TableHeader, Box<dyn error::Error>> {
+    let len = f.seek(SeekFrom::End(0))?;
+    f.seek(SeekFrom::Start(0))?;
+
+    let mut version = None;
+    let mut count = f.read_i32::<LittleEndian>()?;
+    if count == FORMAT_VERSION {
+        version = Some(count);
+        count = f.read_i32::<LittleEndian>()?;
+    }
+    if count < 0 {
+        return Err(format!("Invalid record count {}", count).into());
+    }
+
+    let left = len - f.stream_position()?;
+    let record_size = if count > 0 {
+        left / count as u64 / 4 * 4
+    } else {
+        0
+    };
+    if count > 0 && record_size < record_min {
+        return Err(format!(
+            "{} records don't fit in the remaining {} bytes",
+            count, left
+        )
+        .into());
+    }
+
+    Ok(TableHeader {
+        version,
+        count,
+        record_size,
+    })
+}
+
+pub fn write_table_header<W: Write>(
+    f: &mut W,
+    version: Option<i32>,
+    count: usize,
+) -> Result<(), Box<dyn error::Error>> {
+    if let Some(v) = version {
+        f.write_i32::<LittleEndian>(v)?;
+    }
+    f.write_i32::<LittleEndian>(count as i32)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the encoding can only be set once per process, every test here uses cp949
+    fn round_trip(buf: &[u8]) -> String {
+        let _ = LEGACY_ENCODING.set(encoding_rs::EUC_KR);
+        let name = decode_name(buf);
+        assert_eq!(encode_name(&name), buf, "{:?}", name);
+        name
+    }
+
+    #[test]
+    fn utf8_names() {
+        assert_eq!(round_trip(b"models/man.box"), "models/man.box");
+        // plain text would be written as cp949, so the UTF-8 bytes stay escaped
+        assert_eq!(round_trip("평양.png".as_bytes()), "%ED%8F%89%EC%96%91.png");
+    }
+
+    #[test]
+    fn cp949_names() {
+        let (buf, _, _) = encoding_rs::EUC_KR.encode("모형/차.box");
+        assert_eq!(round_trip(&buf), "모형/차.box");
+        assert_eq!(encode_name("모형/차.box"), *buf);
+    }
+
+    #[test]
+    fn same_text_from_both_encodings() {
+        // the same in either order, nothing is remembered between names
+        let (legacy, _, _) = encoding_rs::EUC_KR.encode("도로.map");
+        for _ in 0..2 {
+            assert_eq!(round_trip("도로.map".as_bytes()), "%EB%8F%84%EB%A1%9C.map");
+            assert_eq!(round_trip(&legacy), "도로.map");
+        }
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        assert_eq!(round_trip(b"tex\x80\xff.png"), "tex%80%FF.png");
+    }
+
+    #[test]
+    fn uris() {
+        assert_eq!(uri_path("textures/a b.png"), "textures/a%20b.png");
+        assert_eq!(uri_path("평양.png"), "%ED%8F%89%EC%96%91.png");
+        assert_eq!(uri_path("bad%FF#1.png"), "bad%25FF%231.png");
+    }
+
+    #[test]
+    fn percent_signs() {
+        assert_eq!(round_trip(b"100%.png"), "100%.png");
+        assert_eq!(round_trip(b"a%41.png"), "a%2541.png");
+        assert_eq!(round_trip(b"%"), "%");
+    }
+
+    #[test]
+    fn full_fixed_strings() {
+        // no room for the NULL, but read_fixed_string takes it so it has to pass
+        let name = "a".repeat(NAME_LEN);
+        let mut buf = vec![];
+        write_fixed_string(&mut buf, &name, &[], NAME_LEN).unwrap();
+        let (back, tail) = read_fixed_string(&mut buf.as_slice(), NAME_LEN).unwrap();
+        assert_eq!((back.as_str(), tail.len()), (name.as_str(), 0));
+        assert!(fixed_string_problem("texture", &name, NAME_LEN).is_none());
+        assert!(fixed_string_problem("texture", &format!("{}a", name), NAME_LEN).is_some());
+    }
 }
@@ -0,0 +1,418 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    common,
+    conversion::{copy_texture, Options},
+    mesh::{Corner, Mesh},
+    output::OutputSet,
+};
+
+// Only the parts of glTF 2.0 we write, names come out as the spec wants them through
+// serde. Was scrapped once because the game's separate position/uv indices don't fit,
+// the vertices are split up per corner now so every attribute shares one index.
+
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#reference-accessor
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const LINEAR: u32 = 9729;
+const LINEAR_MIPMAP_LINEAR: u32 = 9987;
+const MIRRORED_REPEAT: u32 = 33648;
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Gltf {
+    asset: Asset,
+    scene: usize,
+    scenes: Vec<Scene>,
+    nodes: Vec<Node>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    meshes: Vec<GltfMesh>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    materials: Vec<Material>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    textures: Vec<Texture>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<Image>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    samplers: Vec<Sampler>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    buffers: Vec<Buffer>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    buffer_views: Vec<BufferView>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    accessors: Vec<Accessor>,
+}
+
+#[derive(Serialize, Default)]
+struct Asset {
+    generator: String,
+    version: String,
+}
+
+#[derive(Serialize)]
+struct Scene {
+    nodes: Vec<usize>,
+}
+
+#[derive(Serialize)]
+struct Node {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mesh: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    translation: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extras: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct GltfMesh {
+    name: String,
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Serialize)]
+struct Primitive {
+    attributes: BTreeMap<&'static str, usize>,
+    indices: usize,
+    material: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    targets: Vec<BTreeMap<&'static str, usize>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Material {
+    name: String,
+    pbr_metallic_roughness: Pbr,
+    double_sided: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extras: Option<Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Pbr {
+    base_color_texture: TextureInfo,
+    metallic_factor: f32,
+    roughness_factor: f32,
+}
+
+#[derive(Serialize)]
+struct TextureInfo {
+    index: usize,
+}
+
+#[derive(Serialize)]
+struct Texture {
+    source: usize,
+    sampler: usize,
+}
+
+#[derive(Serialize)]
+struct Image {
+    uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Sampler {
+    mag_filter: u32,
+    min_filter: u32,
+    wrap_s: u32,
+    wrap_t: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Buffer {
+    uri: String,
+    byte_length: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    byte_offset: usize,
+    byte_length: usize,
+    target: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: usize,
+    component_type: u32,
+    count: usize,
+    #[serde(rename = "type")]
+    type_: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<Vec<f32>>,
+}
+
+// everything goes in one buffer, all our components are 4 bytes so it stays aligned
+#[derive(Default)]
+struct Builder {
+    bin: Vec<u8>,
+    views: Vec<BufferView>,
+    accessors: Vec<Accessor>,
+}
+
+impl Builder {
+    fn push(&mut self, accessor: Accessor, bytes: Vec<u8>, target: u32) -> usize {
+        self.views.push(BufferView {
+            buffer: 0,
+            byte_offset: self.bin.len(),
+            byte_length: bytes.len(),
+            target,
+        });
+        self.bin.extend(bytes);
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn floats(&mut self, data: &[f32], width: usize, type_: &'static str, bounds: bool) -> usize {
+        // positions have to come with their bounds
+        let (min, max) = if bounds {
+            let mut min = vec![f32::MAX; width];
+            let mut max = vec![f32::MIN; width];
+            for x in data.chunks(width) {
+                for i in 0..width {
+                    min[i] = min[i].min(x[i]);
+                    max[i] = max[i].max(x[i]);
+                }
+            }
+            (Some(min), Some(max))
+        } else {
+            (None, None)
+        };
+        let accessor = Accessor {
+            buffer_view: self.views.len(),
+            component_type: FLOAT,
+            count: data.len() / width,
+            type_,
+            min,
+            max,
+        };
+        let bytes = data.iter().flat_map(|x| x.to_le_bytes()).collect();
+        self.push(accessor, bytes, ARRAY_BUFFER)
+    }
+
+    fn indices(&mut self, data: &[u32]) -> usize {
+        let accessor = Accessor {
+            buffer_view: self.views.len(),
+            component_type: UNSIGNED_INT,
+            count: data.len(),
+            type_: "SCALAR",
+            min: None,
+            max: None,
+        };
+        let bytes = data.iter().flat_map(|x| x.to_le_bytes()).collect();
+        self.push(accessor, bytes, ELEMENT_ARRAY_BUFFER)
+    }
+}
+
+fn vec3s(data: impl Iterator<Item = (f32, f32, f32)>) -> Vec<f32> {
+    data.flat_map(|x| [x.0, x.1, x.2]).collect()
+}
+
+fn hints(hints: &[String]) -> Option<Value> {
+    if hints.is_empty() {
+        None
+    } else {
+        Some(json!({ "hints": hints }))
+    }
+}
+
+// one primitive per material, markers become empty nodes next to the mesh.
+// image_uris has one entry per material, paths relative to the output that get
+// percent-encoded here like bin_uri
+fn to_gltf(mesh: &Mesh, name: &str, bin_uri: &str, image_uris: &[String]) -> (Gltf, Vec<u8>) {
+    let mut b = Builder::default();
+    let mut primitives = vec![];
+    for i in 0..mesh.materials.len() {
+        // every distinct corner becomes its own vertex
+        let mut corners: HashMap<Corner, u32> = HashMap::new();
+        let mut order: Vec<Corner> = vec![];
+        let mut indices = vec![];
+        for x in mesh.faces.iter().filter(|x| x.material == i) {
+            for c in &x.corners {
+                let v = *corners.entry(*c).or_insert_with(|| {
+                    order.push(*c);
+                    order.len() as u32 - 1
+                });
+                indices.push(v);
+            }
+        }
+        // accessors can't be empty
+        if indices.is_empty() {
+            continue;
+        }
+
+        let mut attributes = BTreeMap::new();
+        let positions: Vec<_> = order
+            .iter()
+            .map(|c| mesh.positions[c.position as usize])
+            .collect();
+        attributes.insert(
+            "POSITION",
+            b.floats(&vec3s(positions.iter().copied()), 3, "VEC3", true),
+        );
+        if order.iter().all(|c| c.uv.is_some()) {
+            // glTF has v going down
+            let uvs: Vec<f32> = order
+                .iter()
+                .flat_map(|c| {
+                    let uv = mesh.uvs[c.uv.unwrap() as usize];
+                    [uv.0, 1.0 - uv.1]
+                })
+                .collect();
+            attributes.insert("TEXCOORD_0", b.floats(&uvs, 2, "VEC2", false));
+        }
+        if let Some(normals) = &mesh.normals {
+            if order.iter().all(|c| c.normal.is_some()) {
+                let data = vec3s(order.iter().map(|c| normals[c.normal.unwrap() as usize]));
+                attributes.insert("NORMAL", b.floats(&data, 3, "VEC3", false));
+            }
+        }
+
+        // animation frames as morph targets, those are stored as offsets
+        let mut targets = vec![];
+        for frame in &mesh.frames {
+            let data = vec3s(order.iter().zip(&positions).map(|(c, p)| {
+                let v = frame[c.position as usize];
+                (v.0 - p.0, v.1 - p.1, v.2 - p.2)
+            }));
+            targets.push(BTreeMap::from([(
+                "POSITION",
+                b.floats(&data, 3, "VEC3", true),
+            )]));
+        }
+
+        let indices = b.indices(&indices);
+        primitives.push(Primitive {
+            attributes,
+            indices,
+            material: i,
+            targets,
+        });
+    }
+
+    let mut gltf = Gltf {
+        asset: Asset {
+            generator: String::from(env!("CARGO_PKG_NAME")),
+            version: String::from("2.0"),
+        },
+        ..Default::default()
+    };
+    if !mesh.materials.is_empty() {
+        gltf.samplers.push(Sampler {
+            mag_filter: LINEAR,
+            min_filter: LINEAR_MIPMAP_LINEAR,
+            wrap_s: MIRRORED_REPEAT,
+            wrap_t: MIRRORED_REPEAT,
+        });
+    }
+
+    for (i, x) in mesh.materials.iter().enumerate() {
+        gltf.images.push(Image {
+            uri: common::uri_path(&image_uris[i]),
+        });
+        gltf.textures.push(Texture {
+            source: i,
+            sampler: 0,
+        });
+        gltf.materials.push(Material {
+            name: x.texture.clone(),
+            pbr_metallic_roughness: Pbr {
+                base_color_texture: TextureInfo { index: i },
+                metallic_factor: 0.0,
+                roughness_factor: 1.0,
+            },
+            double_sided: true,
+            extras: hints(&x.hints),
+        });
+    }
+
+    // a mesh needs at least one primitive, without faces only the markers are left
+    let has_mesh = !primitives.is_empty();
+    if has_mesh {
+        gltf.meshes.push(GltfMesh {
+            name: name.to_owned(),
+            primitives,
+        });
+    }
+    gltf.nodes.push(Node {
+        name: name.to_owned(),
+        mesh: has_mesh.then_some(0),
+        translation: None,
+        extras: None,
+    });
+    for x in &mesh.markers {
+        gltf.nodes.push(Node {
+            name: x.name.clone(),
+            mesh: None,
+            translation: Some([x.position.0, x.position.1, x.position.2]),
+            extras: hints(&x.hints),
+        });
+    }
+    gltf.scenes.push(Scene {
+        nodes: (0..gltf.nodes.len()).collect(),
+    });
+
+    // same for buffers, and then there's no .bin to write either
+    if !b.bin.is_empty() {
+        gltf.buffers.push(Buffer {
+            uri: common::uri_path(bin_uri),
+            byte_length: b.bin.len(),
+        });
+    }
+    gltf.buffer_views = b.views;
+    gltf.accessors = b.accessors;
+    (gltf, b.bin)
+}
+
+// writes <input>.gltf with its <input>.bin and copies the textures next to them
+pub fn export_mesh(
+    mesh: &Mesh,
+    input: &Path,
+    output: &Path,
+    opts: &Options,
+) -> Result<Vec<PathBuf>, Box<dyn error::Error>> {
+    let in_base = input.file_name().unwrap().to_str().unwrap();
+    let bin_name = format!("{}.bin", in_base);
+
+    let mut out = OutputSet::default();
+    let mat_num = mesh.materials.len();
+    let mut image_uris = vec![];
+    for (i, x) in mesh.materials.iter().enumerate() {
+        let pfx = if mat_num > 1 {
+            format!("[{}/{}] ", i + 1, mat_num)
+        } else {
+            String::new()
+        };
+        image_uris.push(copy_texture(&x.texture, output, opts, &mut out, &pfx));
+    }
+
+    let (gltf, bin) = to_gltf(mesh, in_base, &bin_name, &image_uris);
+    if !bin.is_empty() {
+        out.add(output.join(&bin_name), bin);
+    }
+    out.add(
+        output.join(format!("{}.gltf", in_base)),
+        serde_json::to_vec_pretty(&gltf)?,
+    );
+    out.commit(opts.policy)
+}
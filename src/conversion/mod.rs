@@ -1 +1,251 @@
-pub mod obj;
\ No newline at end of file
+pub mod gltf;
+pub mod obj;
+pub mod textures;
+
+use std::{
+    error, fs,
+    path::{Component, Path, PathBuf},
+};
+
+use rayon::prelude::*;
+use walkdir::WalkDir;
+
+use crate::{
+    archive,
+    output::{OutputSet, Policy, Skipped},
+    registry,
+};
+
+use self::textures::Textures;
+
+// everything about how to convert that isn't the input or where it goes
+#[derive(Default, Clone)]
+pub struct Options {
+    pub textures: Textures,
+    // copied textures keep the folders from their name under the output, so
+    // textures with the same file name don't end up on top of each other
+    pub mirror_textures: bool,
+    pub policy: Policy,
+    // None for the first one the type supports
+    pub format: Option<String>,
+}
+
+// where a texture goes relative to the outputs, only the file name unless the
+// folders are mirrored. anything that could leave the output folder is dropped
+pub fn texture_dest(tex_name: &str, mirror: bool) -> String {
+    let parts: Vec<&str> = tex_name
+        .split(['/', '\\'])
+        .filter(|x| !x.is_empty() && *x != "." && *x != "..")
+        .collect();
+    if mirror {
+        parts.join("/")
+    } else {
+        parts.last().copied().unwrap_or_default().to_owned()
+    }
+}
+
+// adds a texture to the outputs if it can be found, returns the path relative to
+// output the outputs should use for it, also if it wasn't found
+pub fn copy_texture(
+    tex_name: &str,
+    output: &Path,
+    opts: &Options,
+    out: &mut OutputSet,
+    pfx: &str,
+) -> String {
+    let rel = texture_dest(tex_name, opts.mirror_textures);
+    let dest = output.join(&rel);
+    let found = match opts.textures.find(tex_name) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}{}", pfx, e);
+            None
+        }
+    };
+    if let Some((data, from)) = found {
+        println!("{}Found texture \"{}\" in {}", pfx, tex_name, from);
+        out.add_shared(dest, data);
+    } else {
+        // otherwise texture is manual because we don't know where it is
+        println!(
+            "{}Couldn't find texture, copy texture from \"{}\" to {:?} to view with textures",
+            pfx, tex_name, dest
+        );
+    }
+    rel
+}
+
+// converts a file that's already in memory, to the first format its type supports
+// unless told otherwise. name is only used to pick the type and to name the outputs
+pub fn convert_data(
+    name: &str,
+    data: &[u8],
+    output: &Path,
+    opts: &Options,
+) -> Result<(), Box<dyn error::Error>> {
+    let handler = registry::by_name(name)
+        .or_else(|| registry::detect(data))
+        .ok_or_else(|| format!("Don't know what {:?} is", name))?;
+    let targets = handler.export_targets();
+    let target = match opts.format.as_deref() {
+        Some(v) if targets.contains(&v) => v,
+        Some(v) => return Err(format!("Can't convert {} files to {}", handler.name(), v).into()),
+        None => targets
+            .first()
+            .ok_or_else(|| format!("Can't convert {} files", handler.name()))?,
+    };
+    handler.export(data, target, Path::new(name), output, opts)?;
+    Ok(())
+}
+
+// same but straight out of an archive, textures are looked up in the archive too
+// after whatever is already in opts
+pub fn convert_entry(
+    archive_path: &Path,
+    name: &str,
+    output: &Path,
+    opts: &Options,
+) -> Result<(), Box<dyn error::Error>> {
+    let (_, data) = archive::read_entry(archive_path, name)?;
+    let mut opts = opts.clone();
+    opts.textures.add_archive(archive_path);
+    convert_data(name, &data, output, &opts)
+}
+
+pub enum Input {
+    File(PathBuf),
+    Entry(PathBuf, String),
+}
+
+pub struct Job {
+    pub input: Input,
+    // folder the outputs go in
+    pub output: PathBuf,
+}
+
+impl Job {
+    pub fn name(&self) -> String {
+        match &self.input {
+            Input::File(v) => format!("{:?}", v),
+            Input::Entry(a, n) => format!("\"{}:{}\"", a.display(), n),
+        }
+    }
+
+    pub fn run(&self, opts: &Options) -> Result<(), Box<dyn error::Error>> {
+        fs::create_dir_all(&self.output)?;
+        match &self.input {
+            Input::File(v) => {
+                let data = fs::read(v)?;
+                convert_data(v.to_str().unwrap(), &data, &self.output, opts)
+            }
+            Input::Entry(a, n) => convert_entry(a, n, &self.output, opts),
+        }
+    }
+}
+
+fn is_glob(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+// files from walking a folder or a glob only count if we can convert them
+fn convertible(path: &Path) -> bool {
+    path.to_str()
+        .and_then(registry::by_name)
+        .map(|x| !x.export_targets().is_empty())
+        .unwrap_or(false)
+}
+
+// the part of a glob before the first wildcard, matches are mirrored relative to it
+fn glob_base(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|x| !matches!(x, Component::Normal(v) if is_glob(&v.to_string_lossy())))
+        .collect()
+}
+
+// turns a file, archive entry, folder or glob into jobs, folders and globs keep
+// their layout under output
+pub fn collect_jobs(input: &str, output: &Path) -> Result<Vec<Job>, Box<dyn error::Error>> {
+    if let Some((archive_path, name)) = archive::split_entry_path(input) {
+        return Ok(vec![Job {
+            input: Input::Entry(archive_path.to_owned(), name.to_owned()),
+            output: output.to_owned(),
+        }]);
+    }
+
+    let input_path = Path::new(input);
+    if input_path.is_file() {
+        return Ok(vec![Job {
+            input: Input::File(input_path.to_owned()),
+            output: output.to_owned(),
+        }]);
+    }
+
+    let (base, files) = if input_path.is_dir() {
+        let files = WalkDir::new(input_path)
+            .into_iter()
+            .filter_map(|x| x.ok())
+            .filter(|x| x.file_type().is_file())
+            .map(|x| x.into_path())
+            .collect::<Vec<_>>();
+        (input_path.to_owned(), files)
+    } else if is_glob(input) {
+        let files = glob::glob(input)?
+            .filter_map(|x| x.ok())
+            .filter(|x| x.is_file())
+            .collect::<Vec<_>>();
+        (glob_base(input), files)
+    } else {
+        return Err(format!("Input file {:?} does not exist", input_path).into());
+    };
+
+    let mut jobs = vec![];
+    for x in files {
+        if !convertible(&x) {
+            continue;
+        }
+        let rel = x.strip_prefix(&base).unwrap_or(&x);
+        jobs.push(Job {
+            output: output.join(rel.parent().unwrap_or(Path::new(""))),
+            input: Input::File(x),
+        });
+    }
+    Ok(jobs)
+}
+
+// converts everything on all cores and prints what happened at the end
+pub fn convert_jobs(jobs: &[Job], opts: &Options) {
+    let results: Vec<_> = jobs
+        .par_iter()
+        .map(|x| {
+            println!("Converting {}...", x.name());
+            // errors can't leave the thread, only whether it was a skip and the text
+            x.run(opts).map_err(|e| (e.is::<Skipped>(), e.to_string()))
+        })
+        .collect();
+
+    let mut done = 0;
+    let mut skipped = vec![];
+    let mut failed = vec![];
+    for (job, res) in jobs.iter().zip(results) {
+        match res {
+            Ok(()) => done += 1,
+            Err((true, e)) => skipped.push((job.name(), e)),
+            Err((false, e)) => failed.push((job.name(), e)),
+        }
+    }
+
+    println!();
+    for (name, e) in &skipped {
+        println!("Skipped {}: {}", name, e);
+    }
+    for (name, e) in &failed {
+        eprintln!("Failed {}: {}", name, e);
+    }
+    println!(
+        "{} converted, {} skipped, {} failed",
+        done,
+        skipped.len(),
+        failed.len()
+    );
+}
@@ -1,81 +1,110 @@
-use std::io::Write;
+use std::{
+    error,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-use crate::common;
+use crate::{
+    conversion::{copy_texture, Options},
+    mesh::{Corner, Mesh},
+    output::OutputSet,
+};
 
-pub fn write_obj<W: Write>(
-    f: &mut W,
-    mtl: &str,
-    vtx: &Vec<(f32, f32, f32)>,
-    uv: &Vec<(f32, f32)>,
-    tri: &Vec<common::Tri>,
-) -> Result<(), std::io::Error> {
+pub fn write_obj<W: Write>(f: &mut W, mtl: &str, mesh: &Mesh) -> Result<(), std::io::Error> {
     // write material info
-    f.write(format!("mtllib {}\nusemtl default\n", mtl).as_bytes())?;
+    f.write_all(format!("mtllib {}\nusemtl default\n", mtl).as_bytes())?;
     // write points
-    for x in vtx {
+    for x in &mesh.positions {
         let str = format!("v {} {} {}\n", x.0, x.1, x.2);
-        f.write(str.as_bytes())?;
+        f.write_all(str.as_bytes())?;
     }
     // write texture UVs
-    for x in uv {
+    for x in &mesh.uvs {
         let str = format!("vt {} {}\n", x.0, x.1);
-        f.write(str.as_bytes())?;
+        f.write_all(str.as_bytes())?;
+    }
+    // write normals
+    for x in mesh.normals.iter().flatten() {
+        let str = format!("vn {} {} {}\n", x.0, x.1, x.2);
+        f.write_all(str.as_bytes())?;
     }
-    // write faces
-    for x in tri {
+    // write faces, indices start at 1
+    let corner = |c: &Corner| match (c.uv, c.normal) {
+        (Some(t), Some(n)) => format!("{}/{}/{}", c.position + 1, t + 1, n + 1),
+        (Some(t), None) => format!("{}/{}", c.position + 1, t + 1),
+        (None, Some(n)) => format!("{}//{}", c.position + 1, n + 1),
+        (None, None) => format!("{}", c.position + 1),
+    };
+    for x in &mesh.faces {
         let str = format!(
-            "f {}/{} {}/{} {}/{}\n",
-            x.c + 1,
-            x.tc + 1,
-            x.b + 1,
-            x.tb + 1,
-            x.a + 1,
-            x.ta + 1
+            "f {} {} {}\n",
+            corner(&x.corners[0]),
+            corner(&x.corners[1]),
+            corner(&x.corners[2])
         );
-        f.write(str.as_bytes())?;
+        f.write_all(str.as_bytes())?;
     }
     // flush data to output
     f.flush()
 }
 
-pub fn write_obj_alt<W: Write>(
+pub fn write_mtl<W: Write>(
     f: &mut W,
-    mtl: &str,
-    vtx: &Vec<(f32, f32, f32)>,
-    uv: &Vec<(f32, f32)>,
-    tri: &Vec<(u16, u16, u16)>,
+    tex_name: &str,
+    comments: &[String],
 ) -> Result<(), std::io::Error> {
-    // write material info
-    f.write(format!("mtllib {}\nusemtl default\n", mtl).as_bytes())?;
-    // write points
-    for x in vtx {
-        let str = format!("v {} {} {}\n", x.0, x.1, x.2);
-        f.write(str.as_bytes())?;
-    }
-    // write texture UVs
-    for x in uv {
-        let str = format!("vt {} {}\n", x.0, x.1);
-        f.write(str.as_bytes())?;
-    }
-    // write faces
-    for x in tri {
-        let str = format!(
-            "f {}/{} {}/{} {}/{}\n",
-            x.2 + 1,
-            x.2 + 1,
-            x.1 + 1,
-            x.1 + 1,
-            x.0 + 1,
-            x.0 + 1,
-        );
-        f.write(str.as_bytes())?;
+    // hints that aren't confirmed go in as comments
+    for x in comments {
+        f.write_all(format!("# {}\n", x).as_bytes())?;
     }
-    // flush data to output
+    let mtl_str = format!("newmtl default\nKa 1.0 1.0 1.0\nKd 1.0 1.0 1.0\nKs 0.0 0.0 0.0\nTr 0.0\nillum 1\nNs 0.0\nmap_Kd {}", tex_name);
+    f.write_all(mtl_str.as_bytes())?;
     f.flush()
 }
 
-pub fn write_mtl<W: Write>(f: &mut W, tex_name: &str) -> Result<(), std::io::Error> {
-    let mtl_str = format!("newmtl default\nKa 1.0 1.0 1.0\nKd 1.0 1.0 1.0\nKs 0.0 0.0 0.0\nTr 0.0\nillum 1\nNs 0.0\nmap_Kd {}", tex_name);
-    f.write(mtl_str.as_bytes())?;
-    f.flush()
+// writes one .obj/.mtl pair per material (<input>.<i>.obj, or <input>.obj if there's
+// only one) and copies the textures next to them if they can be found
+pub fn export_mesh(
+    mesh: &Mesh,
+    input: &Path,
+    output: &Path,
+    opts: &Options,
+) -> Result<Vec<PathBuf>, Box<dyn error::Error>> {
+    let mat_num = mesh.materials.len();
+
+    // path stuff time!!!!
+    let in_base = input.file_name().unwrap().to_str().unwrap();
+    let f_name = |i: usize, ext: &str| {
+        if mat_num > 1 {
+            format!("{}.{}.{}", in_base, i, ext)
+        } else {
+            format!("{}.{}", in_base, ext)
+        }
+    };
+
+    let mut out = OutputSet::default();
+    for i in 0..mat_num {
+        let part = mesh.submesh(i);
+        let tex_name = &part.materials[0].texture;
+
+        // logging stuff
+        let pfx = if mat_num > 1 {
+            format!("[{}/{}] ", i + 1, mat_num)
+        } else {
+            String::new()
+        };
+
+        // first object
+        let mtl_f_name = f_name(i, "mtl");
+        let mut obj_buf = vec![];
+        write_obj(&mut obj_buf, &mtl_f_name, &part)?;
+        out.add(output.join(f_name(i, "obj")), obj_buf);
+
+        // then material, pointing at wherever the texture is copied to
+        let tex_path = copy_texture(tex_name, output, opts, &mut out, &pfx);
+        let mut mtl_buf = vec![];
+        write_mtl(&mut mtl_buf, &tex_path, &part.materials[0].hints)?;
+        out.add(output.join(mtl_f_name), mtl_buf);
+    }
+    out.commit(opts.policy)
 }
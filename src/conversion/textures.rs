@@ -0,0 +1,153 @@
+use std::{
+    collections::HashMap,
+    error, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use walkdir::WalkDir;
+
+use crate::{archive, seek};
+
+// Where converters look for the textures a model points at, searched in the order
+// they were added. Names are compared case-insensitive with / and \ treated the same
+// since the game runs on windows and the files don't agree with each other.
+pub enum Source {
+    // extracted tree, texture names are relative to it
+    Dir(PathBuf, OnceLock<HashMap<String, PathBuf>>),
+    // the textures of an archive, only looked at the first time something is needed
+    Archive(PathBuf, OnceLock<Result<ArchiveTextures, String>>),
+}
+
+// normalized name -> real entry name, with an index the data is only inflated when
+// the texture is used, otherwise all of them are read at once
+pub enum ArchiveTextures {
+    Indexed(Arc<seek::Indexed>, HashMap<String, String>),
+    Read(HashMap<String, (String, Vec<u8>)>),
+}
+
+// texture data and where it came from
+pub type Found = (Vec<u8>, String);
+
+// cheap to clone, clones share what was already read
+#[derive(Default, Clone)]
+pub struct Textures {
+    sources: Vec<Arc<Source>>,
+}
+
+// texture type id, see registry
+const TEXTURE_TYPE: u8 = 10;
+
+fn normalize(name: &str) -> String {
+    let name = name.replace('\\', "/").to_lowercase();
+    let mut name = name.as_str();
+    while let Some(v) = name.strip_prefix("./").or_else(|| name.strip_prefix('/')) {
+        name = v;
+    }
+    name.to_owned()
+}
+
+fn read_textures(path: &Path) -> Result<ArchiveTextures, Box<dyn error::Error>> {
+    if let Some(index) = seek::Indexed::open(path)? {
+        let mut names = HashMap::new();
+        for x in index.entries().filter(|x| x.type_ == TEXTURE_TYPE) {
+            names
+                .entry(normalize(&x.name))
+                .or_insert_with(|| x.name.clone());
+        }
+        return Ok(ArchiveTextures::Indexed(index, names));
+    }
+
+    let mut textures = HashMap::new();
+    let mut rdr = archive::ArchiveReader::open(path)?;
+    while let Some((i, data)) = rdr.next_entry()? {
+        let entry = &rdr.entries[i];
+        if entry.type_ == TEXTURE_TYPE {
+            textures
+                .entry(normalize(&entry.name))
+                .or_insert((entry.name.clone(), data));
+        }
+    }
+    Ok(ArchiveTextures::Read(textures))
+}
+
+fn index_dir(root: &Path) -> HashMap<String, PathBuf> {
+    let mut files = HashMap::new();
+    for x in WalkDir::new(root).into_iter().filter_map(|x| x.ok()) {
+        if !x.file_type().is_file() {
+            continue;
+        }
+        if let Some(v) = x.path().strip_prefix(root).ok().and_then(|v| v.to_str()) {
+            files.entry(normalize(v)).or_insert(x.path().to_owned());
+        }
+    }
+    files
+}
+
+impl Textures {
+    pub fn add_dir(&mut self, path: &Path) {
+        self.sources
+            .push(Arc::new(Source::Dir(path.to_owned(), OnceLock::new())));
+    }
+
+    pub fn add_archive(&mut self, path: &Path) {
+        self.sources
+            .push(Arc::new(Source::Archive(path.to_owned(), OnceLock::new())));
+    }
+
+    // directory or .dat file
+    pub fn add_root(&mut self, path: &Path) -> Result<(), Box<dyn error::Error>> {
+        if path.is_dir() {
+            self.add_dir(path);
+        } else if path.is_file()
+            && path
+                .extension()
+                .map(|x| x.eq_ignore_ascii_case("dat"))
+                .unwrap_or(false)
+        {
+            self.add_archive(path);
+        } else {
+            return Err(format!("Root {:?} is not a directory or .dat file", path).into());
+        }
+        Ok(())
+    }
+
+    // data of the first match and where it was found, None if no source has it
+    pub fn find(&self, name: &str) -> Result<Option<Found>, Box<dyn error::Error>> {
+        let key = normalize(name);
+        for x in &self.sources {
+            match x.as_ref() {
+                Source::Dir(root, files) => {
+                    // exact path first, no need to walk the tree if it's right. names
+                    // come from the files being converted, so only if they stay inside
+                    if let Ok(path) = archive::safe_path(name).map(|x| root.join(x)) {
+                        if path.is_file() {
+                            return Ok(Some((fs::read(&path)?, format!("{:?}", path))));
+                        }
+                    }
+                    if let Some(path) = files.get_or_init(|| index_dir(root)).get(&key) {
+                        return Ok(Some((fs::read(path)?, format!("{:?}", path))));
+                    }
+                }
+                Source::Archive(path, textures) => {
+                    let textures = textures
+                        .get_or_init(|| read_textures(path).map_err(|e| e.to_string()))
+                        .as_ref()
+                        .map_err(|e| format!("Couldn't read textures from {:?}: {}", path, e))?;
+                    let found = match textures {
+                        ArchiveTextures::Indexed(index, names) => match names.get(&key) {
+                            Some(v) => Some((v.clone(), index.read(v)?.1)),
+                            None => None,
+                        },
+                        ArchiveTextures::Read(v) => v.get(&key).cloned(),
+                    };
+                    if let Some((entry, data)) = found {
+                        let from = format!("\"{}:{}\"", path.display(), entry);
+                        return Ok(Some((data, from)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    error, fs,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::{self, Entry},
+    output, registry,
+};
+
+// Small changes to an archive without unpacking it. Everything goes through in one
+// pass, entries that aren't touched keep their data, place and type id.
+
+pub enum Edit {
+    // name, type id and the file with the data
+    Add(String, u8, PathBuf),
+    Remove(String),
+    Replace(String, PathBuf),
+    Rename(String, String),
+}
+
+// where the data of an entry in the new archive comes from
+enum Source {
+    Old(usize),
+    File(PathBuf),
+}
+
+fn find(plan: &[(Entry, Source)], name: &str) -> Option<usize> {
+    plan.iter().position(|x| x.0.name == name)
+}
+
+fn find_old(plan: &[(Entry, Source)], name: &str) -> Result<usize, Box<dyn error::Error>> {
+    find(plan, name).ok_or_else(|| format!("There is no entry \"{}\"", name).into())
+}
+
+// new names have to be unpackable and not taken yet
+fn check_new(plan: &[(Entry, Source)], name: &str) -> Result<(), Box<dyn error::Error>> {
+    if let Err(e) = archive::safe_path(name) {
+        return Err(format!("Entry name \"{}\" {}", name, e).into());
+    }
+    if find(plan, name).is_some() {
+        return Err(format!("There already is an entry \"{}\"", name).into());
+    }
+    Ok(())
+}
+
+pub fn edit(
+    archive_path: &Path,
+    output: Option<String>,
+    edits: Vec<Edit>,
+) -> Result<(), Box<dyn error::Error>> {
+    // in place unless told otherwise
+    let out_file = match output {
+        Some(v) => PathBuf::from(v),
+        None => archive_path.to_owned(),
+    };
+
+    let mut rdr = archive::ArchiveReader::open(archive_path)?;
+    let mut plan: Vec<(Entry, Source)> = rdr
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x.clone(), Source::Old(i)))
+        .collect();
+    let old_problems = archive::order_problems(&rdr.entries).len();
+
+    for x in edits {
+        match x {
+            Edit::Add(name, type_, file) => {
+                check_new(&plan, &name)?;
+                println!(
+                    "Adding \"{}\", type: {} ({})",
+                    name,
+                    type_,
+                    registry::type_name(type_)
+                );
+                let pos = archive::insert_position(plan.iter().map(|v| &v.0), type_);
+                plan.insert(pos, (Entry { name, type_ }, Source::File(file)));
+            }
+            Edit::Remove(name) => {
+                let i = find_old(&plan, &name)?;
+                println!("Removing \"{}\"", name);
+                plan.remove(i);
+            }
+            Edit::Replace(name, file) => {
+                let i = find_old(&plan, &name)?;
+                println!("Replacing \"{}\" with {:?}", name, file);
+                plan[i].1 = Source::File(file);
+            }
+            Edit::Rename(name, new_name) => {
+                let i = find_old(&plan, &name)?;
+                check_new(&plan, &new_name)?;
+                // the type id stays, but pack would go by the name
+                if let Some(v) = registry::by_name(&new_name) {
+                    if v.type_id() != plan[i].0.type_ {
+                        eprintln!(
+                            "Warning: \"{}\" keeps type {}, but the name says {}",
+                            new_name,
+                            registry::type_name(plan[i].0.type_),
+                            v.name()
+                        );
+                    }
+                }
+                println!("Renaming \"{}\" to \"{}\"", name, new_name);
+                plan[i].0.name = new_name;
+            }
+        }
+    }
+
+    // only complain about what the edits broke, not what was broken already
+    let entries: Vec<Entry> = plan.iter().map(|x| x.0.clone()).collect();
+    let problems = archive::order_problems(&entries);
+    if problems.len() > old_problems {
+        for x in &problems {
+            eprintln!("{}", x);
+        }
+        return Err("Textures would come after models".into());
+    }
+
+    println!("Writing {} entries to {:?}...", entries.len(), out_file);
+    output::write_atomic_with(&out_file, |f| {
+        let mut w = archive::ArchiveWriter::new(BufWriter::new(f), &entries)?;
+        for (entry, source) in &plan {
+            match source {
+                Source::Old(i) => {
+                    // skips what was removed or replaced on the way
+                    loop {
+                        let (j, data) = rdr
+                            .next_entry()?
+                            .ok_or("Archive ended before all entries were read")?;
+                        if j == *i {
+                            w.add(&data)?;
+                            break;
+                        }
+                    }
+                }
+                Source::File(path) => {
+                    let data = fs::read(path)?;
+                    // broken files are allowed like in pack, but it's good to know
+                    if let Some(Err(e)) = registry::by_type(entry.type_).map(|x| x.parse(&data)) {
+                        eprintln!("Warning: {:?} doesn't look valid: {}", path, e);
+                    }
+                    w.add(&data)?;
+                }
+            }
+        }
+        w.finish()?.into_inner().map_err(|e| e.into_error())?;
+        Ok(())
+    })
+}
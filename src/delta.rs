@@ -0,0 +1,313 @@
+use std::{
+    collections::HashMap,
+    error, fs,
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{archive, common, output};
+
+// Patches between two archives, made on what's inside the deflate stream so it
+// doesn't matter how either of them was compressed. A patch is one raw deflate
+// stream, in it is:
+//
+//   8 bytes magic
+//   64 bytes hex content hash of the archive it applies to
+//   64 bytes hex content hash of the result
+//   u64 result length
+//   ops until the end:
+//     u8 0, u64 offset, u64 length   copy from the source
+//     u8 1, u32 length, data         new bytes, longer runs are split
+//
+// Content hashes are the same as archive::content_hash.
+
+const MAGIC: &[u8; 8] = b"PRDELTA1";
+const HASH_LEN: usize = 64;
+const COPY: u8 = 0;
+const INSERT: u8 = 1;
+
+// smallest match worth a copy, anything shorter goes in as new bytes
+const BLOCK: usize = 32;
+// rolling hash multiplier
+const PRIME: u64 = 0x100000001b3;
+// most new bytes one op can hold
+const MAX_INSERT: usize = u32::MAX as usize;
+
+enum Op {
+    Copy(u64, u64),
+    Insert(Vec<u8>),
+}
+
+fn block_hash(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0u64, |h, x| h.wrapping_mul(PRIME).wrapping_add(*x as u64))
+}
+
+// copies of every block of the source that shows up in the target, the rest
+// is inserted. the source is only indexed at block boundaries, the target is
+// searched at every offset with a rolling hash
+fn make_ops(src: &[u8], dst: &[u8]) -> Vec<Op> {
+    let mut index: HashMap<u64, usize> = HashMap::new();
+    for (i, x) in src.chunks_exact(BLOCK).enumerate() {
+        index.entry(block_hash(x)).or_insert(i * BLOCK);
+    }
+    // PRIME^(BLOCK-1), to take the byte leaving the window back out
+    let out_factor = (1..BLOCK).fold(1u64, |f, _| f.wrapping_mul(PRIME));
+
+    let mut ops = vec![];
+    // start of bytes that didn't match anything yet
+    let mut pending = 0;
+    let mut t = 0;
+    let mut h = if dst.len() >= BLOCK {
+        block_hash(&dst[..BLOCK])
+    } else {
+        0
+    };
+    while t + BLOCK <= dst.len() {
+        let found = index
+            .get(&h)
+            .copied()
+            .filter(|s| src[*s..*s + BLOCK] == dst[t..t + BLOCK]);
+        if let Some(mut s) = found {
+            // grow the match both ways
+            let mut start = t;
+            while start > pending && s > 0 && src[s - 1] == dst[start - 1] {
+                start -= 1;
+                s -= 1;
+            }
+            let mut len = t + BLOCK - start;
+            while s + len < src.len() && start + len < dst.len() && src[s + len] == dst[start + len]
+            {
+                len += 1;
+            }
+            if start > pending {
+                ops.push(Op::Insert(dst[pending..start].to_vec()));
+            }
+            ops.push(Op::Copy(s as u64, len as u64));
+            t = start + len;
+            pending = t;
+            if t + BLOCK <= dst.len() {
+                h = block_hash(&dst[t..t + BLOCK]);
+            }
+            continue;
+        }
+        if t + BLOCK < dst.len() {
+            h = h
+                .wrapping_sub((dst[t] as u64).wrapping_mul(out_factor))
+                .wrapping_mul(PRIME)
+                .wrapping_add(dst[t + BLOCK] as u64);
+        }
+        t += 1;
+    }
+    if pending < dst.len() {
+        ops.push(Op::Insert(dst[pending..].to_vec()));
+    }
+    ops
+}
+
+pub fn create(
+    source: &Path,
+    target: &Path,
+    output: Option<String>,
+) -> Result<(), Box<dyn error::Error>> {
+    let out_file = match output {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(format!(
+            "{}.delta",
+            target.file_stem().unwrap().to_str().unwrap()
+        )),
+    };
+    if out_file.try_exists()? {
+        return Err(format!("Output file {:?} already exists", out_file).into());
+    }
+
+    let src = archive::read_stream(source)?;
+    let dst = archive::read_stream(target)?;
+    let ops = make_ops(&src, &dst);
+
+    let mut comp = DeflateEncoder::new(vec![], Compression::best());
+    comp.write_all(MAGIC)?;
+    comp.write_all(common::hash_hex(&src).as_bytes())?;
+    comp.write_all(common::hash_hex(&dst).as_bytes())?;
+    comp.write_u64::<LittleEndian>(dst.len() as u64)?;
+    write_ops(&mut comp, &ops)?;
+    let patch = comp.finish()?;
+    let copied: u64 = ops
+        .iter()
+        .map(|x| match x {
+            Op::Copy(_, len) => *len,
+            Op::Insert(_) => 0,
+        })
+        .sum();
+
+    println!(
+        "{} of {} bytes taken from {:?}, {} new",
+        copied,
+        dst.len(),
+        source,
+        dst.len() as u64 - copied
+    );
+    println!("Writing {} byte patch to {:?}...", patch.len(), out_file);
+    output::write_atomic(&out_file, &patch)?;
+    Ok(())
+}
+
+fn write_ops<W: Write>(w: &mut W, ops: &[Op]) -> Result<(), Box<dyn error::Error>> {
+    for x in ops {
+        match x {
+            Op::Copy(offset, len) => {
+                w.write_u8(COPY)?;
+                w.write_u64::<LittleEndian>(*offset)?;
+                w.write_u64::<LittleEndian>(*len)?;
+            }
+            Op::Insert(data) => {
+                for part in data.chunks(MAX_INSERT) {
+                    w.write_u8(INSERT)?;
+                    w.write_u32::<LittleEndian>(part.len() as u32)?;
+                    w.write_all(part)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// the result of the ops in r, up to its end
+fn apply_ops(r: &mut Cursor<Vec<u8>>, src: &[u8]) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    let mut dst = vec![];
+    let end = r.get_ref().len() as u64;
+    while r.position() < end {
+        match r.read_u8()? {
+            COPY => {
+                let offset = r.read_u64::<LittleEndian>()? as usize;
+                let len = r.read_u64::<LittleEndian>()? as usize;
+                let part = offset
+                    .checked_add(len)
+                    .and_then(|x| src.get(offset..x))
+                    .ok_or("Patch copies from outside the archive")?;
+                dst.extend_from_slice(part);
+            }
+            INSERT => {
+                let len = r.read_u32::<LittleEndian>()? as u64;
+                // the length isn't trusted with memory, only what's really there is read
+                if r.take(len).read_to_end(&mut dst)? as u64 != len {
+                    return Err("Patch ends inside new bytes".into());
+                }
+            }
+            x => return Err(format!("Unknown patch op {}", x).into()),
+        }
+    }
+    Ok(dst)
+}
+
+fn read_hash<R: Read>(r: &mut R) -> Result<String, Box<dyn error::Error>> {
+    let mut buf = [0u8; HASH_LEN];
+    r.read_exact(&mut buf)?;
+    Ok(String::from_utf8(buf.to_vec())?)
+}
+
+pub fn apply(
+    source: &Path,
+    patch: &Path,
+    output: Option<String>,
+) -> Result<(), Box<dyn error::Error>> {
+    let out_file = match output {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(format!(
+            "{}_patched.dat",
+            source.file_stem().unwrap().to_str().unwrap()
+        )),
+    };
+    if out_file.try_exists()? {
+        return Err(format!("Output file {:?} already exists", out_file).into());
+    }
+
+    let mut data = vec![];
+    DeflateDecoder::new(fs::File::open(patch)?).read_to_end(&mut data)?;
+    let mut r = Cursor::new(data);
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(format!("{:?} is not a patch", patch).into());
+    }
+    let src_hash = read_hash(&mut r)?;
+    let dst_hash = read_hash(&mut r)?;
+    let dst_len = r.read_u64::<LittleEndian>()?;
+
+    let src = archive::read_stream(source)?;
+    if common::hash_hex(&src) != src_hash {
+        return Err(format!("{:?} is not the archive the patch was made for", source).into());
+    }
+
+    let dst = apply_ops(&mut r, &src)?;
+    if dst.len() as u64 != dst_len || common::hash_hex(&dst) != dst_hash {
+        return Err("Patched archive doesn't match the patch's hash".into());
+    }
+    println!("Writing {:?}...", out_file);
+    output::write_atomic(&out_file, &archive::compress(&dst)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(src: &[u8], dst: &[u8]) -> Vec<Op> {
+        let ops = make_ops(src, dst);
+        let mut buf = vec![];
+        write_ops(&mut buf, &ops).unwrap();
+        assert_eq!(apply_ops(&mut Cursor::new(buf), src).unwrap(), dst);
+        ops
+    }
+
+    fn sample(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty() {
+        assert!(round_trip(&[], &[]).is_empty());
+        round_trip(&sample(100, 1), &[]);
+        round_trip(&[], &sample(100, 1));
+    }
+
+    #[test]
+    fn identical() {
+        let data = sample(10_000, 1);
+        let ops = round_trip(&data, &data);
+        assert!(matches!(ops[..], [Op::Copy(0, 10_000)]));
+    }
+
+    #[test]
+    fn fully_different() {
+        let ops = round_trip(&sample(10_000, 1), &sample(10_000, 2));
+        assert!(ops.iter().all(|x| matches!(x, Op::Insert(_))));
+    }
+
+    #[test]
+    fn changed_in_the_middle() {
+        let src = sample(10_000, 1);
+        let mut dst = src.clone();
+        dst.splice(5000..5010, sample(100, 3));
+        round_trip(&src, &dst);
+    }
+
+    #[test]
+    fn truncated_insert() {
+        let mut buf = vec![];
+        write_ops(&mut buf, &[Op::Insert(vec![1, 2, 3])]).unwrap();
+        buf.pop();
+        assert!(apply_ops(&mut Cursor::new(buf), &[]).is_err());
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error, io,
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    archive::{self, Loaded},
+    common, registry,
+};
+
+// What changed between two archives: which entries were added or removed, moved,
+// changed type or payload. For types the registry can summarize the changed values
+// are listed too, so a changed model says what about it changed.
+
+#[derive(Serialize)]
+struct FieldDiff {
+    name: String,
+    // None when only one side has it
+    a: Option<String>,
+    b: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EntryDiff {
+    name: String,
+    // added, removed or changed
+    status: &'static str,
+    // position in each archive
+    index: (Option<usize>, Option<usize>),
+    #[serde(rename = "type")]
+    type_: (Option<u8>, Option<u8>),
+    size: (Option<usize>, Option<usize>),
+    hash: (Option<String>, Option<String>),
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<FieldDiff>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    a: String,
+    b: String,
+    // entries that are in both but in a different order, textures have to stay
+    // in front of what uses them so this matters
+    moved: Vec<String>,
+    entries: Vec<EntryDiff>,
+    unchanged: usize,
+}
+
+// names can show up more than once, the n-th one is compared with the n-th one
+fn keyed(entries: &[Loaded]) -> Vec<(String, usize)> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    entries
+        .iter()
+        .map(|(x, _)| {
+            let n = seen.entry(&x.name).or_default();
+            *n += 1;
+            (x.name.clone(), *n)
+        })
+        .collect()
+}
+
+fn diff_fields(a: &Loaded, b: &Loaded) -> Vec<FieldDiff> {
+    // both have to be the same type to compare anything
+    if a.0.type_ != b.0.type_ {
+        return vec![];
+    }
+    let handler = match registry::by_type(a.0.type_) {
+        Some(v) => v,
+        None => return vec![],
+    };
+    let sa = handler.summary(&a.1);
+    let sb = handler.summary(&b.1);
+    let ma: HashMap<&String, &String> = sa.iter().map(|x| (&x.0, &x.1)).collect();
+    let mb: HashMap<&String, &String> = sb.iter().map(|x| (&x.0, &x.1)).collect();
+
+    // in a's order, then what only b has
+    let mut fields = vec![];
+    for (name, va) in &sa {
+        match mb.get(name) {
+            Some(vb) if *vb == va => (),
+            vb => fields.push(FieldDiff {
+                name: name.clone(),
+                a: Some(va.clone()),
+                b: vb.map(|x| x.to_string()),
+            }),
+        }
+    }
+    for (name, vb) in &sb {
+        if !ma.contains_key(name) {
+            fields.push(FieldDiff {
+                name: name.clone(),
+                a: None,
+                b: Some(vb.clone()),
+            });
+        }
+    }
+    fields
+}
+
+fn side(e: Option<(usize, &Loaded)>) -> (Option<usize>, Option<u8>, Option<usize>, Option<String>) {
+    match e {
+        Some((i, (x, data))) => (
+            Some(i),
+            Some(x.type_),
+            Some(data.len()),
+            Some(common::hash_hex(data)),
+        ),
+        None => (None, None, None, None),
+    }
+}
+
+fn compare(a_path: &Path, b_path: &Path) -> Result<Report, Box<dyn error::Error>> {
+    let a = archive::read_all(a_path)?;
+    let b = archive::read_all(b_path)?;
+    let a_keys = keyed(&a);
+    let b_keys = keyed(&b);
+    let b_index: HashMap<&(String, usize), usize> =
+        b_keys.iter().enumerate().map(|(i, x)| (x, i)).collect();
+    let a_index: HashMap<&(String, usize), usize> =
+        a_keys.iter().enumerate().map(|(i, x)| (x, i)).collect();
+
+    let mut entries = vec![];
+    let mut unchanged = 0;
+    // a's order first, then whatever only b has
+    let mut order: Vec<(Option<usize>, Option<usize>)> = a_keys
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (Some(i), b_index.get(x).copied()))
+        .collect();
+    order.extend(
+        b_keys
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| !a_index.contains_key(x))
+            .map(|(i, _)| (None, Some(i))),
+    );
+
+    for (ia, ib) in order {
+        let ea = ia.map(|i| (i, &a[i]));
+        let eb = ib.map(|i| (i, &b[i]));
+        let (index_a, type_a, size_a, hash_a) = side(ea);
+        let (index_b, type_b, size_b, hash_b) = side(eb);
+        let (name, status, fields) = match (ea, eb) {
+            (Some((_, x)), Some((_, y))) => {
+                if type_a == type_b && hash_a == hash_b {
+                    unchanged += 1;
+                    continue;
+                }
+                (x.0.name.clone(), "changed", diff_fields(x, y))
+            }
+            (Some((_, x)), None) => (x.0.name.clone(), "removed", vec![]),
+            (None, Some((_, y))) => (y.0.name.clone(), "added", vec![]),
+            (None, None) => unreachable!(),
+        };
+        entries.push(EntryDiff {
+            name,
+            status,
+            index: (index_a, index_b),
+            type_: (type_a, type_b),
+            size: (size_a, size_b),
+            hash: (hash_a, hash_b),
+            fields,
+        });
+    }
+
+    // the common entries in b's order, anything out of place compared to a moved.
+    // only the ones that aren't part of the longest run already in order count
+    let common: Vec<usize> = b_keys
+        .iter()
+        .filter_map(|x| a_index.get(x).copied())
+        .collect();
+    let keep: HashSet<usize> = longest_increasing(&common).into_iter().collect();
+    let moved = common
+        .iter()
+        .filter(|x| !keep.contains(x))
+        .map(|x| a[*x].0.name.clone())
+        .collect();
+
+    Ok(Report {
+        a: a_path.display().to_string(),
+        b: b_path.display().to_string(),
+        moved,
+        entries,
+        unchanged,
+    })
+}
+
+// values of the longest increasing subsequence
+fn longest_increasing(v: &[usize]) -> Vec<usize> {
+    // index into v of the smallest tail of every length, and where each came from
+    let mut tails: Vec<usize> = vec![];
+    let mut prev = vec![None; v.len()];
+    for i in 0..v.len() {
+        let n = tails.partition_point(|x| v[*x] < v[i]);
+        if n > 0 {
+            prev[i] = Some(tails[n - 1]);
+        }
+        if n == tails.len() {
+            tails.push(i);
+        } else {
+            tails[n] = i;
+        }
+    }
+    let mut out = vec![];
+    let mut cur = tails.last().copied();
+    while let Some(i) = cur {
+        out.push(v[i]);
+        cur = prev[i];
+    }
+    out.reverse();
+    out
+}
+
+fn type_str(t: Option<u8>) -> String {
+    match t {
+        Some(v) => format!("{} ({})", registry::type_name(v), v),
+        None => String::from("-"),
+    }
+}
+
+fn opt_str<T: ToString>(v: &Option<T>) -> String {
+    v.as_ref()
+        .map(|x| x.to_string())
+        .unwrap_or_else(|| String::from("-"))
+}
+
+pub fn diff(a: &Path, b: &Path, json: bool) -> Result<(), Box<dyn error::Error>> {
+    let report = compare(a, b)?;
+
+    if json {
+        serde_json::to_writer_pretty(io::stdout(), &report)?;
+        println!();
+        return Ok(());
+    }
+
+    for x in &report.entries {
+        match x.status {
+            "added" => println!(
+                "+ \"{}\", length: {}, type: {}",
+                x.name,
+                opt_str(&x.size.1),
+                type_str(x.type_.1)
+            ),
+            "removed" => println!(
+                "- \"{}\", length: {}, type: {}",
+                x.name,
+                opt_str(&x.size.0),
+                type_str(x.type_.0)
+            ),
+            _ => {
+                println!("~ \"{}\"", x.name);
+                if x.type_.0 != x.type_.1 {
+                    println!(
+                        "    type: {} -> {}",
+                        type_str(x.type_.0),
+                        type_str(x.type_.1)
+                    );
+                }
+                if x.size.0 != x.size.1 {
+                    println!(
+                        "    length: {} -> {}",
+                        opt_str(&x.size.0),
+                        opt_str(&x.size.1)
+                    );
+                }
+                for f in &x.fields {
+                    println!("    {}: {} -> {}", f.name, opt_str(&f.a), opt_str(&f.b));
+                }
+                if x.size.0 == x.size.1 && x.fields.is_empty() {
+                    println!("    contents differ");
+                }
+            }
+        }
+    }
+    for x in &report.moved {
+        println!("> \"{}\" moved", x);
+    }
+
+    let count = |s: &str| report.entries.iter().filter(|x| x.status == s).count();
+    println!(
+        "{} unchanged, {} changed, {} added, {} removed, {} moved",
+        report.unchanged,
+        count("changed"),
+        count("added"),
+        count("removed"),
+        report.moved.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,264 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error, fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive, common,
+    conversion::Options,
+    output::{self, Policy, Skipped},
+    registry,
+};
+
+// Everything in an archive in one go, for people who just want to look around:
+//
+//   raw/        every entry as it is in the archive
+//   converted/  every model in all formats we can write, textures next to them
+//   index.md    what's in there, also as index.html
+//
+// Maps are converted like models, the level with its object positions as empty
+// nodes. What goes at those isn't known yet, so levels aren't put together with
+// their objects. Failed conversions don't stop anything, they end up in the index.
+
+struct IndexEntry {
+    name: String,
+    type_: u8,
+    size: usize,
+    references: Vec<String>,
+    // relative to the export folder
+    files: Vec<String>,
+    error: Option<String>,
+}
+
+fn rel_str(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .to_str()
+        .unwrap()
+        .replace('\\', "/")
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_md(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+fn write_md(
+    path: &Path,
+    title: &str,
+    entries: &[IndexEntry],
+    used_by: &BTreeMap<String, Vec<String>>,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut f = fs::File::create(path)?;
+    writeln!(f, "# {}\n", escape_md(title))?;
+    writeln!(f, "{} entries\n", entries.len())?;
+    writeln!(f, "| Name | Type | Size | References | Used by | Files |")?;
+    writeln!(f, "| --- | --- | ---: | --- | --- | --- |")?;
+    for x in entries {
+        let link = |v: &String| format!("[{}]({})", escape_md(v), common::uri_path(v));
+        let mut files: Vec<String> = x.files.iter().map(link).collect();
+        if let Some(e) = &x.error {
+            files.push(escape_md(e));
+        }
+        writeln!(
+            f,
+            "| {} | {} ({}) | {} | {} | {} | {} |",
+            escape_md(&x.name),
+            registry::type_name(x.type_),
+            x.type_,
+            x.size,
+            escape_md(&x.references.join(", ")),
+            escape_md(
+                &used_by
+                    .get(&x.name.to_lowercase())
+                    .cloned()
+                    .unwrap_or_default()
+                    .join(", ")
+            ),
+            files.join("<br>")
+        )?;
+    }
+    f.flush()?;
+    Ok(())
+}
+
+fn write_html(
+    path: &Path,
+    title: &str,
+    entries: &[IndexEntry],
+    used_by: &BTreeMap<String, Vec<String>>,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut f = fs::File::create(path)?;
+    let title = escape_html(title);
+    writeln!(
+        f,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>",
+        title
+    )?;
+    writeln!(f, "<style>body {{ font-family: sans-serif; }} table {{ border-collapse: collapse; }} td, th {{ border: 1px solid #ccc; padding: 2px 6px; text-align: left; vertical-align: top; }}</style>")?;
+    writeln!(
+        f,
+        "</head>\n<body>\n<h1>{}</h1>\n<p>{} entries</p>",
+        title,
+        entries.len()
+    )?;
+    writeln!(f, "<table>\n<tr><th>Name</th><th>Type</th><th>Size</th><th>References</th><th>Used by</th><th>Files</th></tr>")?;
+    for x in entries {
+        let mut files: Vec<String> = x
+            .files
+            .iter()
+            .map(|v| {
+                let href = common::uri_path(v);
+                format!("<a href=\"{}\">{}</a>", escape_html(&href), escape_html(v))
+            })
+            .collect();
+        if let Some(e) = &x.error {
+            files.push(escape_html(e));
+        }
+        writeln!(
+            f,
+            "<tr><td>{}</td><td>{} ({})</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&x.name),
+            registry::type_name(x.type_),
+            x.type_,
+            x.size,
+            escape_html(&x.references.join(", ")),
+            escape_html(
+                &used_by
+                    .get(&x.name.to_lowercase())
+                    .cloned()
+                    .unwrap_or_default()
+                    .join(", ")
+            ),
+            files.join("<br>")
+        )?;
+    }
+    writeln!(f, "</table>\n</body>\n</html>")?;
+    f.flush()?;
+    Ok(())
+}
+
+pub fn export_all(
+    input: &Path,
+    output: Option<String>,
+    roots: &[String],
+    mut opts: Options,
+) -> Result<(), Box<dyn error::Error>> {
+    let out_dir = match output {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(format!(
+            "{}_export",
+            input.file_stem().unwrap().to_str().unwrap()
+        )),
+    };
+    if opts.policy == Policy::Abort && out_dir.try_exists()? {
+        return Err(format!("{:?} already exists", out_dir).into());
+    }
+
+    // textures from the roots first, then the archive itself
+    for x in roots {
+        opts.textures.add_root(Path::new(x))?;
+    }
+    opts.textures.add_archive(input);
+
+    let raw_dir = out_dir.join("raw");
+    let conv_dir = out_dir.join("converted");
+    let mut entries = vec![];
+    let mut rdr = archive::ArchiveReader::open(input)?;
+    let checks = archive::check_names(&rdr.entries);
+    while let Some((i, data)) = rdr.next_entry()? {
+        let o = &rdr.entries[i];
+        println!(
+            "\"{}\", length: {}, type: {} ({})",
+            o.name,
+            data.len(),
+            o.type_,
+            registry::type_name(o.type_)
+        );
+
+        let mut entry = IndexEntry {
+            name: o.name.clone(),
+            type_: o.type_,
+            size: data.len(),
+            references: vec![],
+            files: vec![],
+            error: None,
+        };
+
+        // names that would end up outside the export only make it into the index
+        for x in &checks[i].warnings {
+            eprintln!("Warning: {}", x);
+        }
+        let rel = match &checks[i].path {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Not exporting \"{}\", it {}", o.name, e);
+                entry.error = Some(format!("not exported, the name {}", e));
+                entries.push(entry);
+                continue;
+            }
+        };
+
+        let raw_path = raw_dir.join(rel);
+        if output::should_write(opts.policy, &raw_path, &data)? {
+            output::write_atomic(&raw_path, &data)?;
+        }
+        entry.files.push(rel_str(&raw_path, &out_dir));
+
+        if let Some(handler) = registry::by_type(o.type_) {
+            entry.references = handler.references(&data);
+
+            // converted files go in the same folder the entry is in
+            let dest = conv_dir.join(rel);
+            let dest = dest.parent().unwrap();
+            let mut files = BTreeSet::new();
+            for target in handler.export_targets() {
+                fs::create_dir_all(dest)?;
+                // what was written, or what a policy kept from a run before
+                match handler.export(&data, target, rel, dest, &opts) {
+                    Ok(v) => files.extend(v),
+                    Err(e) => match e.downcast_ref::<Skipped>() {
+                        Some(v) => {
+                            println!("{}", v);
+                            files.extend(v.existing.iter().cloned());
+                        }
+                        None => {
+                            eprintln!("Couldn't convert \"{}\" to {}: {}", o.name, target, e);
+                            entry.error = Some(format!("conversion failed: {}", e));
+                        }
+                    },
+                }
+            }
+            entry
+                .files
+                .extend(files.iter().map(|x| rel_str(x, &out_dir)));
+        }
+        entries.push(entry);
+    }
+
+    // the other way around, so textures and models show what uses them
+    let mut used_by: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for x in &entries {
+        for r in &x.references {
+            let key = r.replace('\\', "/").to_lowercase();
+            used_by.entry(key).or_default().push(x.name.clone());
+        }
+    }
+
+    let title = input.file_name().unwrap().to_str().unwrap();
+    println!("Writing index to {:?}...", out_dir.join("index.md"));
+    write_md(&out_dir.join("index.md"), title, &entries, &used_by)?;
+    write_html(&out_dir.join("index.html"), title, &entries, &used_by)?;
+
+    let failed = entries.iter().filter(|x| x.error.is_some()).count();
+    println!("{} entries exported, {} with errors", entries.len(), failed);
+    Ok(())
+}
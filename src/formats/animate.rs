@@ -0,0 +1,327 @@
+use std::{
+    error, fs,
+    io::{Cursor, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{common, formats::r#box, registry::AssetHandler};
+
+// There is no reader for this one in the original code we have, so the layout is
+// pieced together from the file itself. It's a table (see common::read_table_header)
+// where each record is, as far as we can tell:
+//
+//   char[100] model, maybe the .box the animation plays on
+//   i32 maybe first frame
+//   i32 maybe last frame
+//   whatever is left of the record, kept as raw words
+//
+// None of the meanings are confirmed, the names are guesses and the values are
+// only ever shown and written back raw.
+//
+// Anything after the last record is kept as well so writing it back gives the
+// exact same file.
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Animate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+    pub records: Vec<AnimateRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailing: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnimateRecord {
+    pub model: String,
+    // garbage after the NULL in the name field, if any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub model_tail: Vec<u8>,
+    // guessed names, meaning unconfirmed
+    pub first_frame: i32,
+    pub last_frame: i32,
+    // unidentified words, stored as bits so floats survive the round trip
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra: Vec<u32>,
+}
+
+// name + first frame + last frame
+pub const RECORD_MIN: u64 = common::NAME_LEN as u64 + 8;
+
+pub fn read<R: Read + Seek>(f: &mut R) -> Result<Animate, Box<dyn error::Error>> {
+    let header = common::read_table_header(f, RECORD_MIN)?;
+
+    let mut records = vec![];
+    for _ in 0..header.count {
+        let (model, model_tail) = common::read_fixed_string(f, common::NAME_LEN)?;
+        let first_frame = f.read_i32::<LittleEndian>()?;
+        let last_frame = f.read_i32::<LittleEndian>()?;
+        let mut extra = vec![];
+        for _ in 0..(header.record_size - RECORD_MIN) / 4 {
+            extra.push(f.read_u32::<LittleEndian>()?);
+        }
+        records.push(AnimateRecord {
+            model,
+            model_tail,
+            first_frame,
+            last_frame,
+            extra,
+        });
+    }
+
+    let mut trailing = vec![];
+    f.read_to_end(&mut trailing)?;
+
+    Ok(Animate {
+        version: header.version,
+        records,
+        trailing,
+    })
+}
+
+pub fn write<W: Write>(f: &mut W, anim: &Animate) -> Result<(), Box<dyn error::Error>> {
+    // the game reads fixed size records, so they all need the same amount of words
+    if let Some(first) = anim.records.first() {
+        for (i, x) in anim.records.iter().enumerate() {
+            if x.extra.len() != first.extra.len() {
+                return Err(format!(
+                    "Record {} has {} extra words, expected {} like the first record",
+                    i,
+                    x.extra.len(),
+                    first.extra.len()
+                )
+                .into());
+            }
+        }
+    }
+
+    common::write_table_header(f, anim.version, anim.records.len())?;
+    for x in &anim.records {
+        common::write_fixed_string(f, &x.model, &x.model_tail, common::NAME_LEN)?;
+        f.write_i32::<LittleEndian>(x.first_frame)?;
+        f.write_i32::<LittleEndian>(x.last_frame)?;
+        for w in &x.extra {
+            f.write_u32::<LittleEndian>(*w)?;
+        }
+    }
+    f.write_all(&anim.trailing)?;
+    f.flush()?;
+    Ok(())
+}
+
+// find the model a record points at, names are relative to the archive root
+// but sometimes it's just the file name
+fn find_model(root: &Path, name: &str) -> Option<PathBuf> {
+    let direct = root.join(name);
+    if direct.is_file() {
+        return Some(direct);
+    }
+    let base = Path::new(name).file_name()?.to_str()?.to_lowercase();
+    WalkDir::new(root)
+        .into_iter()
+        .filter_map(|x| x.ok())
+        .find(|x| {
+            x.file_type().is_file()
+                && x.file_name().to_str().map(|n| n.to_lowercase()) == Some(base.clone())
+        })
+        .map(|x| x.into_path())
+}
+
+pub fn show(input: &Path, root: Option<String>) -> Result<(), Box<dyn error::Error>> {
+    let mut f = fs::File::open(input)?;
+    let anim = read(&mut f).map_err(|e| format!("Couldn't read {:?}: {}", input, e))?;
+
+    // models are looked up next to the file unless told otherwise
+    let root = match root {
+        Some(v) => PathBuf::from(v),
+        None => match input.parent() {
+            Some(v) if !v.as_os_str().is_empty() => v.to_owned(),
+            _ => PathBuf::from("."),
+        },
+    };
+
+    println!("{} records", anim.records.len());
+    for (i, x) in anim.records.iter().enumerate() {
+        let status = match find_model(&root, &x.model) {
+            None => String::from("model not found"),
+            Some(p) => match fs::File::open(&p)
+                .map_err(|e| e.into())
+                .and_then(|mut mf| r#box::read_header(&mut mf))
+            {
+                Err(e) => format!("{:?}: {}", p, e),
+                // the frame fields are a guess, so this is only a hint
+                Ok(h) => {
+                    if x.first_frame < 0
+                        || x.first_frame > x.last_frame
+                        || x.last_frame >= h.frame_num
+                    {
+                        format!(
+                            "{:?}: model has {} frames, doesn't look like a frame range",
+                            p, h.frame_num
+                        )
+                    } else {
+                        format!("{:?}: model has {} frames", p, h.frame_num)
+                    }
+                }
+            },
+        };
+        println!(
+            "[{}] \"{}\", first_frame {}, last_frame {}, extra: {:?} (raw values, meaning unconfirmed; {})",
+            i, x.model, x.first_frame, x.last_frame, x.extra, status
+        );
+    }
+    if !anim.trailing.is_empty() {
+        println!("{} trailing bytes", anim.trailing.len());
+    }
+    Ok(())
+}
+
+pub fn export(input: &Path, output: Option<String>) -> Result<(), Box<dyn error::Error>> {
+    let out_path = match output {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(format!("{}.json", input.to_str().unwrap())),
+    };
+    if out_path.try_exists()? {
+        return Err(format!("Output file {:?} already exists", out_path).into());
+    }
+
+    let data = fs::read(input)?;
+    let anim =
+        read(&mut Cursor::new(&data)).map_err(|e| format!("Couldn't read {:?}: {}", input, e))?;
+    let text = serde_json::to_string_pretty(&anim)?;
+
+    // make sure the json gives the same file back before handing it out
+    let back: Animate = serde_json::from_str(&text)?;
+    let mut check = vec![];
+    write(&mut check, &back)?;
+    if check != data {
+        return Err(format!("Round trip check failed for {:?}", input).into());
+    }
+
+    println!(
+        "Writing {} records to {:?}...",
+        anim.records.len(),
+        out_path
+    );
+    fs::write(out_path, text)?;
+    Ok(())
+}
+
+pub fn import(input: &Path, output: Option<String>) -> Result<(), Box<dyn error::Error>> {
+    let out_path = match output {
+        Some(v) => PathBuf::from(v),
+        None => input.with_extension(""),
+    };
+    if out_path.try_exists()? {
+        return Err(format!("Output file {:?} already exists", out_path).into());
+    }
+
+    let text = fs::read_to_string(input)?;
+    let anim: Animate =
+        serde_json::from_str(&text).map_err(|e| format!("Couldn't parse {:?}: {}", input, e))?;
+
+    let mut buf = vec![];
+    write(&mut buf, &anim)?;
+
+    println!(
+        "Writing {} records to {:?}...",
+        anim.records.len(),
+        out_path
+    );
+    fs::write(out_path, buf)?;
+    Ok(())
+}
+
+pub struct AnimateHandler;
+
+impl AssetHandler for AnimateHandler {
+    fn type_id(&self) -> u8 {
+        5
+    }
+    fn name(&self) -> &'static str {
+        "animate"
+    }
+    fn file_names(&self) -> &'static [&'static str] {
+        &["animate.dat"]
+    }
+    fn parse(&self, data: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        read(&mut Cursor::new(data))?;
+        Ok(())
+    }
+    fn references(&self, data: &[u8]) -> Vec<String> {
+        let mut refs: Vec<String> = match read(&mut Cursor::new(data)) {
+            Ok(v) => v.records.into_iter().map(|x| x.model).collect(),
+            Err(_) => vec![],
+        };
+        refs.sort();
+        refs.dedup();
+        refs
+    }
+    fn summary(&self, data: &[u8]) -> Vec<(String, String)> {
+        let f = match read(&mut Cursor::new(data)) {
+            Ok(v) => v,
+            Err(e) => return vec![(String::from("error"), e.to_string())],
+        };
+        let mut s = vec![(String::from("records"), f.records.len().to_string())];
+        for (i, x) in f.records.iter().enumerate() {
+            s.push((format!("records[{}].model", i), format!("{:?}", x.model)));
+            s.push((
+                format!("records[{}].first_frame", i),
+                x.first_frame.to_string(),
+            ));
+            s.push((
+                format!("records[{}].last_frame", i),
+                x.last_frame.to_string(),
+            ));
+        }
+        s
+    }
+    fn write(&self, data: &[u8]) -> Option<Result<Vec<u8>, Box<dyn error::Error>>> {
+        let res = read(&mut Cursor::new(data)).and_then(|x| {
+            let mut out = vec![];
+            write(&mut out, &x)?;
+            Ok(out)
+        });
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two records with a tail after a name, odd frame values and extra words
+    fn vanilla() -> Vec<u8> {
+        let mut data = vec![];
+        common::write_table_header(&mut data, Some(common::FORMAT_VERSION), 2).unwrap();
+        for (model, tail, first, last) in [
+            ("flag.box", &b"junk"[..], 0, 7),
+            ("man.box", &[][..], -1, i32::MAX),
+        ] {
+            common::write_fixed_string(&mut data, model, tail, common::NAME_LEN).unwrap();
+            data.write_i32::<LittleEndian>(first).unwrap();
+            data.write_i32::<LittleEndian>(last).unwrap();
+            data.write_u32::<LittleEndian>(0x7fc00001).unwrap();
+            data.write_u32::<LittleEndian>(3).unwrap();
+        }
+        data.extend_from_slice(b"end");
+        data
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let dir = std::env::temp_dir().join(format!("animate_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("animate.dat");
+        let text = dir.join("animate.dat.json");
+        let back = dir.join("back.dat");
+        fs::write(&input, vanilla()).unwrap();
+        export(&input, Some(text.to_str().unwrap().to_owned())).unwrap();
+        import(&text, Some(back.to_str().unwrap().to_owned())).unwrap();
+        assert_eq!(fs::read(&back).unwrap(), vanilla());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,152 +1,242 @@
 use std::{
-    fs,
-    io::{Read, Seek, SeekFrom},
-    path::{Path, PathBuf},
+    error,
+    io::{Cursor, Read, Seek, SeekFrom},
 };
 
 use byteorder::{LittleEndian, ReadBytesExt};
 
-use crate::{common, conversion::obj};
+use crate::{
+    common,
+    mesh::{self, Corner, Face, Mesh},
+    registry::AssetHandler,
+};
 
-pub fn box_to_obj<R: Read + Seek>(mut f: R, input: &Path, output: &Path, root: Option<String>) {
-    //
-    // ReadHeader
-    //
-    let magic = f.read_i32::<LittleEndian>().unwrap();
-    let version = f.read_i32::<LittleEndian>().unwrap();
+pub const MAGIC: i32 = 1112496128;
+
+pub struct BoxHeader {
+    pub lump_num: i32,
+    pub lump_off: i32,
+    pub frame_num: i32,
+    pub box_off: i32,
+}
+
+pub struct Lump {
+    pub pos: i32,
+    pub vtx_num: i32,
+    pub tri_num: i32,
+    pub uvs_num: i32,
+    pub tex_name: String,
+    pub tex_tail: Vec<u8>,
+    pub uvs: Vec<(f32, f32)>,
+    pub tris: Vec<common::Tri>,
+    // every frame has its own copy of all vertices
+    pub frames: Vec<Vec<(f32, f32, f32)>>,
+}
+
+pub struct BoxFile {
+    pub header: BoxHeader,
+    pub lumps: Vec<Lump>,
+}
+
+// the first lump record comes right after the header
+pub const HEADER_LEN: u64 = 24;
+
+// pos, vertex, triangle and uv count, texture name
+pub const LUMP_LEN: u64 = 16 + common::NAME_LEN as u64;
+
+pub fn read_header<R: Read>(f: &mut R) -> Result<BoxHeader, Box<dyn error::Error>> {
+    let magic = f.read_i32::<LittleEndian>()?;
+    let version = f.read_i32::<LittleEndian>()?;
 
     // not present in original code
-    if magic != 1112496128 || version != common::FORMAT_VERSION {
-        eprintln!("Invalid magic or version, exiting...");
-        return;
+    if magic != MAGIC || version != common::FORMAT_VERSION {
+        return Err("Invalid magic or version".into());
     }
 
-    let lump_num = f.read_i32::<LittleEndian>().unwrap();
-    f.read_i32::<LittleEndian>().unwrap(); // unused, lump_off
-    let frame_num = f.read_i32::<LittleEndian>().unwrap();
-    f.read_i32::<LittleEndian>().unwrap(); // unused, box_off
+    let header = BoxHeader {
+        lump_num: f.read_i32::<LittleEndian>()?,
+        lump_off: f.read_i32::<LittleEndian>()?,
+        frame_num: f.read_i32::<LittleEndian>()?,
+        box_off: f.read_i32::<LittleEndian>()?,
+    };
+    if header.lump_num < 0 || header.frame_num < 0 {
+        return Err("Negative lump or frame count".into());
+    }
+    Ok(header)
+}
+
+pub fn read<R: Read + Seek>(f: &mut R) -> Result<BoxFile, Box<dyn error::Error>> {
+    //
+    // ReadHeader
+    //
+    let header = read_header(f)?;
+
+    // the counts come straight from the file, so they're checked against what's
+    // actually there before anything is looped over or allocated
+    let len = f.seek(SeekFrom::End(0))?;
+    f.seek(SeekFrom::Start(HEADER_LEN))?;
+    let fits = |from: u64, size: u64| size <= len.saturating_sub(from);
+    if header.frame_num < 1 {
+        return Err("Model has no frames".into());
+    }
+    // frames of empty lumps take no space, this just keeps them from being silly
+    if !fits(HEADER_LEN, header.frame_num as u64)
+        || !fits(HEADER_LEN, header.lump_num as u64 * LUMP_LEN)
+    {
+        return Err("Lump or frame count doesn't fit in the file".into());
+    }
 
     //
     // Readlumps
     //
-    for i in 0..lump_num {
-        let pos = f.read_i32::<LittleEndian>().unwrap();
-        let vtx_num = f.read_i32::<LittleEndian>().unwrap();
-        let tri_num = f.read_i32::<LittleEndian>().unwrap();
-        let uvs_num = f.read_i32::<LittleEndian>().unwrap();
-
-        // due to this string being static size i need to trim excess NULLs at the end
-        let tex_name = common::read_string(&mut f, 100)
-            .unwrap()
-            .trim_end_matches('\0')
-            .to_owned();
+    let mut lumps = vec![];
+    for i in 0..header.lump_num {
+        let pos = f.read_i32::<LittleEndian>()?;
+        let vtx_num = f.read_i32::<LittleEndian>()?;
+        let tri_num = f.read_i32::<LittleEndian>()?;
+        let uvs_num = f.read_i32::<LittleEndian>()?;
+        if vtx_num < 0 || tri_num < 0 || uvs_num < 0 {
+            return Err("Negative vertex, triangle or uv count".into());
+        }
+        let size = (uvs_num as u64 * 8)
+            .saturating_add(tri_num as u64 * 24)
+            .saturating_add((header.frame_num as u64 * vtx_num as u64).saturating_mul(12));
+        if pos < 0 || !fits(pos as u64, size) {
+            return Err(format!("Lump {} doesn't fit in the file", i).into());
+        }
+
+        let (tex_name, tex_tail) = common::read_fixed_string(f, common::NAME_LEN)?;
 
         //
         // ReadpolyMesh
         //
-        f.seek(SeekFrom::Start(pos as u64)).unwrap();
+        f.seek(SeekFrom::Start(pos as u64))?;
 
-        let mut uvs_buf = vec![];
+        let mut uvs = vec![];
         for _ in 0..uvs_num {
-            uvs_buf.push((
-                f.read_f32::<LittleEndian>().unwrap(),
-                1.0 - f.read_f32::<LittleEndian>().unwrap(),
-            ));
+            uvs.push((f.read_f32::<LittleEndian>()?, f.read_f32::<LittleEndian>()?));
         }
 
-        let mut tri_buf = vec![];
+        let mut tris = vec![];
         for _ in 0..tri_num {
-            tri_buf.push(common::Tri {
-                a: f.read_i32::<LittleEndian>().unwrap(),
-                ta: f.read_i32::<LittleEndian>().unwrap(),
-                b: f.read_i32::<LittleEndian>().unwrap(),
-                tb: f.read_i32::<LittleEndian>().unwrap(),
-                c: f.read_i32::<LittleEndian>().unwrap(),
-                tc: f.read_i32::<LittleEndian>().unwrap(),
+            tris.push(common::Tri {
+                a: f.read_i32::<LittleEndian>()?,
+                ta: f.read_i32::<LittleEndian>()?,
+                b: f.read_i32::<LittleEndian>()?,
+                tb: f.read_i32::<LittleEndian>()?,
+                c: f.read_i32::<LittleEndian>()?,
+                tc: f.read_i32::<LittleEndian>()?,
             });
         }
 
-        let mut frame_buf = vec![];
-        for _ in 0..frame_num {
+        let mut frames = vec![];
+        for _ in 0..header.frame_num {
             let mut vtx_buf = vec![];
             for _ in 0..vtx_num {
                 vtx_buf.push((
-                    f.read_f32::<LittleEndian>().unwrap(),
-                    f.read_f32::<LittleEndian>().unwrap(),
-                    f.read_f32::<LittleEndian>().unwrap(),
+                    f.read_f32::<LittleEndian>()?,
+                    f.read_f32::<LittleEndian>()?,
+                    f.read_f32::<LittleEndian>()?,
                 ));
             }
-            frame_buf.push(vtx_buf);
+            frames.push(vtx_buf);
         }
 
-        // logging stuff
-        let pfx = if lump_num > 1 {
-            format!("[{}/{}] ", i + 1, lump_num)
-        } else {
-            String::new()
-        };
+        lumps.push(Lump {
+            pos,
+            vtx_num,
+            tri_num,
+            uvs_num,
+            tex_name,
+            tex_tail,
+            uvs,
+            tris,
+            frames,
+        });
+    }
 
-        // figure out file path stuff
-        let in_base = input.file_name().unwrap().to_str().unwrap();
+    Ok(BoxFile { header, lumps })
+}
 
-        let obj_f_name = {
-            if lump_num > 1 {
-                format!("{}.{}.obj", in_base, i)
-            } else {
-                format!("{}.obj", in_base)
-            }
-        };
-        let mtl_f_name = {
-            if lump_num > 1 {
-                format!("{}.{}.mtl", in_base, i)
-            } else {
-                format!("{}.mtl", in_base)
+// every lump becomes a material, their vertices are put one after another
+pub fn to_mesh(box_file: &BoxFile) -> Result<Mesh, Box<dyn error::Error>> {
+    if box_file.header.frame_num < 1 {
+        return Err("Model has no frames".into());
+    }
+
+    let mut mesh = Mesh {
+        frames: vec![vec![]; box_file.header.frame_num as usize - 1],
+        ..Default::default()
+    };
+    for (i, lump) in box_file.lumps.iter().enumerate() {
+        let pos_base = mesh.positions.len();
+        let uv_base = mesh.uvs.len();
+
+        let corner = |v: i32, t: i32| -> Result<Corner, Box<dyn error::Error>> {
+            if v < 0 || v >= lump.vtx_num || t < 0 || t >= lump.uvs_num {
+                return Err(format!("Lump {} has a triangle pointing outside of it", i).into());
             }
+            Ok(Corner {
+                position: (pos_base + v as usize) as u32,
+                uv: Some((uv_base + t as usize) as u32),
+                normal: None,
+            })
         };
-
-        // check if outputs already exist
-        let obj_f_path = output.join(obj_f_name);
-        if obj_f_path.try_exists().unwrap() {
-            eprintln!("Output file {:?} already exists, exiting...", obj_f_path);
-            return;
+        for x in &lump.tris {
+            mesh.faces.push(Face {
+                corners: [corner(x.c, x.tc)?, corner(x.b, x.tb)?, corner(x.a, x.ta)?],
+                material: i,
+            });
         }
-        let mtl_f_path = output.join(&mtl_f_name);
-        if mtl_f_path.try_exists().unwrap() {
-            eprintln!("Output file {:?} already exists, exiting...", mtl_f_path);
-            return;
+
+        mesh.positions.extend_from_slice(&lump.frames[0]);
+        for (frame, x) in mesh.frames.iter_mut().zip(&lump.frames[1..]) {
+            frame.extend_from_slice(x);
         }
+        mesh.uvs.extend(lump.uvs.iter().map(|x| (x.0, 1.0 - x.1)));
+        mesh.materials.push(mesh::Material {
+            texture: lump.tex_name.clone(),
+            hints: vec![],
+        });
+    }
+    Ok(mesh)
+}
 
-        // first write object
-        println!("{}Writing object (.obj) to {:?}...", pfx, obj_f_path);
-        let mut obj_f = fs::File::create(obj_f_path).unwrap();
-        obj::write_obj(&mut obj_f, &mtl_f_name, &frame_buf[0], &uvs_buf, &tri_buf).unwrap();
-
-        // then write material
-        println!("{}Writing material (.mtl) to {:?}...", pfx, mtl_f_path);
-        let mut mtl_f = fs::File::create(mtl_f_path).unwrap();
-        obj::write_mtl(&mut mtl_f, &tex_name).unwrap();
-
-        // check if we can auto copy texture
-        let tex_base = Path::new(&tex_name).file_name().unwrap().to_str().unwrap();
-        let tex_path = match root {
-            Some(ref v) => Path::new(v).join(&tex_name),
-            None => PathBuf::from(&tex_name),
-        };
-        if tex_path.is_file() {
-            // check if we already did
-            let dest = output.join(&tex_base);
-            if dest.try_exists().unwrap() && dest.is_file() {
-                println!("{}Skipping copying texture, already exists", pfx);
-            } else {
-                println!("{}Copying texture to {:?}...", pfx, dest);
-                fs::copy(tex_path, dest).unwrap();
-            }
-        } else {
-            // otherwise texture is manual because we don't know where it is
-            println!(
-                "{}Couldn't find texture, copy texture from \"{}\" to view with textures",
-                pfx, tex_name
-            );
+pub struct BoxHandler;
+
+impl AssetHandler for BoxHandler {
+    fn type_id(&self) -> u8 {
+        0
+    }
+    fn name(&self) -> &'static str {
+        "box"
+    }
+    fn extensions(&self) -> &'static [&'static str] {
+        &["box"]
+    }
+    fn detect(&self, data: &[u8]) -> bool {
+        data.starts_with(&MAGIC.to_le_bytes())
+    }
+    fn parse(&self, data: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        to_mesh(&read(&mut Cursor::new(data))?)?;
+        Ok(())
+    }
+    fn limits(&self, data: &[u8]) -> Vec<String> {
+        match read(&mut Cursor::new(data)) {
+            Ok(x) => x
+                .lumps
+                .iter()
+                .filter_map(|l| {
+                    common::fixed_string_problem("texture", &l.tex_name, common::NAME_LEN)
+                })
+                .collect(),
+            Err(_) => vec![],
         }
     }
+    fn to_mesh(&self, data: &[u8]) -> Option<Result<Mesh, Box<dyn error::Error>>> {
+        Some(read(&mut Cursor::new(data)).and_then(|x| to_mesh(&x)))
+    }
+    fn export_targets(&self) -> &'static [&'static str] {
+        &["obj", "gltf"]
+    }
 }
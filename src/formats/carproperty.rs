@@ -0,0 +1,327 @@
+use std::{
+    error, fs,
+    io::{Cursor, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{common, registry::AssetHandler};
+
+// Like animate.dat this one isn't handled anywhere in the original code we have,
+// it's a table (see common::read_table_header) with one record per car:
+//
+//   char[100] model, the .box used for the car body
+//   f32 unknown_f32_0, maybe top speed
+//   f32 unknown_f32_1, maybe acceleration
+//   f32 unknown_f32_2, maybe handling (how fast it turns)
+//   f32 unknown_f32_3, maybe braking
+//   whatever is left of the record, kept as raw words
+//
+// Nothing we have reads the floats and what they do isn't known, the "maybe"s are
+// guesses without anything to back them. Until someone confirms it they stay unknown
+// in the files people edit, the guesses are only shown next to them by inspect.
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CarProperty {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+    pub cars: Vec<Car>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailing: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Car {
+    pub model: String,
+    // garbage after the NULL in the name field, if any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub model_tail: Vec<u8>,
+    #[serde(
+        serialize_with = "common::serialize_f32",
+        deserialize_with = "common::deserialize_f32"
+    )]
+    pub unknown_f32_0: f32,
+    #[serde(
+        serialize_with = "common::serialize_f32",
+        deserialize_with = "common::deserialize_f32"
+    )]
+    pub unknown_f32_1: f32,
+    #[serde(
+        serialize_with = "common::serialize_f32",
+        deserialize_with = "common::deserialize_f32"
+    )]
+    pub unknown_f32_2: f32,
+    #[serde(
+        serialize_with = "common::serialize_f32",
+        deserialize_with = "common::deserialize_f32"
+    )]
+    pub unknown_f32_3: f32,
+    // unidentified words, stored as bits so floats survive the round trip
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra: Vec<u32>,
+}
+
+// name, an unconfirmed guess of what it is, value
+pub type Field = (&'static str, &'static str, f32);
+
+impl Car {
+    pub fn fields(&self) -> [Field; 4] {
+        [
+            ("unknown_f32_0", "top speed", self.unknown_f32_0),
+            ("unknown_f32_1", "acceleration", self.unknown_f32_1),
+            ("unknown_f32_2", "handling", self.unknown_f32_2),
+            ("unknown_f32_3", "braking", self.unknown_f32_3),
+        ]
+    }
+}
+
+// name + 4 floats
+pub const RECORD_MIN: u64 = common::NAME_LEN as u64 + 16;
+
+pub fn read<R: Read + Seek>(f: &mut R) -> Result<CarProperty, Box<dyn error::Error>> {
+    let header = common::read_table_header(f, RECORD_MIN)?;
+
+    let mut cars = vec![];
+    for _ in 0..header.count {
+        let (model, model_tail) = common::read_fixed_string(f, common::NAME_LEN)?;
+        let unknown_f32_0 = f.read_f32::<LittleEndian>()?;
+        let unknown_f32_1 = f.read_f32::<LittleEndian>()?;
+        let unknown_f32_2 = f.read_f32::<LittleEndian>()?;
+        let unknown_f32_3 = f.read_f32::<LittleEndian>()?;
+        let mut extra = vec![];
+        for _ in 0..(header.record_size - RECORD_MIN) / 4 {
+            extra.push(f.read_u32::<LittleEndian>()?);
+        }
+        cars.push(Car {
+            model,
+            model_tail,
+            unknown_f32_0,
+            unknown_f32_1,
+            unknown_f32_2,
+            unknown_f32_3,
+            extra,
+        });
+    }
+
+    let mut trailing = vec![];
+    f.read_to_end(&mut trailing)?;
+
+    Ok(CarProperty {
+        version: header.version,
+        cars,
+        trailing,
+    })
+}
+
+pub fn write<W: Write>(f: &mut W, props: &CarProperty) -> Result<(), Box<dyn error::Error>> {
+    // the game reads fixed size records, so they all need the same amount of words
+    if let Some(first) = props.cars.first() {
+        for (i, x) in props.cars.iter().enumerate() {
+            if x.extra.len() != first.extra.len() {
+                return Err(format!(
+                    "Car {} has {} extra words, expected {} like the first car",
+                    i,
+                    x.extra.len(),
+                    first.extra.len()
+                )
+                .into());
+            }
+        }
+    }
+
+    common::write_table_header(f, props.version, props.cars.len())?;
+    for x in &props.cars {
+        common::write_fixed_string(f, &x.model, &x.model_tail, common::NAME_LEN)?;
+        f.write_f32::<LittleEndian>(x.unknown_f32_0)?;
+        f.write_f32::<LittleEndian>(x.unknown_f32_1)?;
+        f.write_f32::<LittleEndian>(x.unknown_f32_2)?;
+        f.write_f32::<LittleEndian>(x.unknown_f32_3)?;
+        for w in &x.extra {
+            f.write_u32::<LittleEndian>(*w)?;
+        }
+    }
+    f.write_all(&props.trailing)?;
+    f.flush()?;
+    Ok(())
+}
+
+// returns everything the game couldn't read back, empty if everything is fine.
+// the floats can be anything, nobody knows yet what the game accepts
+pub fn validate(props: &CarProperty) -> Vec<String> {
+    let mut problems = vec![];
+    for (i, x) in props.cars.iter().enumerate() {
+        if let Some(v) = common::fixed_string_problem("Model", &x.model, common::NAME_LEN) {
+            problems.push(format!("Car {}: {}", i, v));
+        }
+    }
+    problems
+}
+
+// text format is picked from the extension, toml unless it says json
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .map(|x| x.eq_ignore_ascii_case("json"))
+        .unwrap_or(false)
+}
+
+pub fn export(input: &Path, output: Option<String>) -> Result<(), Box<dyn error::Error>> {
+    let out_path = match output {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(format!("{}.toml", input.to_str().unwrap())),
+    };
+    if out_path.try_exists()? {
+        return Err(format!("Output file {:?} already exists", out_path).into());
+    }
+
+    let data = fs::read(input)?;
+    let props =
+        read(&mut Cursor::new(&data)).map_err(|e| format!("Couldn't read {:?}: {}", input, e))?;
+
+    let text = if is_json(&out_path) {
+        serde_json::to_string_pretty(&props)?
+    } else {
+        toml::to_string_pretty(&props)?
+    };
+
+    // make sure the text gives the same file back before handing it out,
+    // floats are the thing most likely to get mangled on the way
+    let back = parse(&text, &out_path)?;
+    let mut check = vec![];
+    write(&mut check, &back)?;
+    if check != data {
+        return Err(format!("Round trip check failed for {:?}", input).into());
+    }
+
+    println!("Writing {} cars to {:?}...", props.cars.len(), out_path);
+    fs::write(out_path, text)?;
+    Ok(())
+}
+
+fn parse(text: &str, path: &Path) -> Result<CarProperty, Box<dyn error::Error>> {
+    let parsed: Result<CarProperty, Box<dyn error::Error>> = if is_json(path) {
+        serde_json::from_str(text).map_err(|e| e.into())
+    } else {
+        toml::from_str(text).map_err(|e| e.into())
+    };
+    parsed.map_err(|e| format!("Couldn't parse {:?}: {}", path, e).into())
+}
+
+pub fn import(input: &Path, output: Option<String>) -> Result<(), Box<dyn error::Error>> {
+    let out_path = match output {
+        Some(v) => PathBuf::from(v),
+        None => input.with_extension(""),
+    };
+    if out_path.try_exists()? {
+        return Err(format!("Output file {:?} already exists", out_path).into());
+    }
+
+    let props = parse(&fs::read_to_string(input)?, input)?;
+
+    let problems = validate(&props);
+    if !problems.is_empty() {
+        for x in problems {
+            eprintln!("{}", x);
+        }
+        return Err(format!("Can't write {:?}", input).into());
+    }
+
+    let mut buf = vec![];
+    write(&mut buf, &props)?;
+
+    println!("Writing {} cars to {:?}...", props.cars.len(), out_path);
+    fs::write(out_path, buf)?;
+    Ok(())
+}
+
+pub struct CarPropertyHandler;
+
+impl AssetHandler for CarPropertyHandler {
+    fn type_id(&self) -> u8 {
+        6
+    }
+    fn name(&self) -> &'static str {
+        "carproperty"
+    }
+    fn file_names(&self) -> &'static [&'static str] {
+        &["carproperty.dat"]
+    }
+    fn parse(&self, data: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        read(&mut Cursor::new(data))?;
+        Ok(())
+    }
+    fn references(&self, data: &[u8]) -> Vec<String> {
+        let mut refs: Vec<String> = match read(&mut Cursor::new(data)) {
+            Ok(v) => v.cars.into_iter().map(|x| x.model).collect(),
+            Err(_) => vec![],
+        };
+        refs.sort();
+        refs.dedup();
+        refs
+    }
+    fn summary(&self, data: &[u8]) -> Vec<(String, String)> {
+        let f = match read(&mut Cursor::new(data)) {
+            Ok(v) => v,
+            Err(e) => return vec![(String::from("error"), e.to_string())],
+        };
+        let mut s = vec![(String::from("cars"), f.cars.len().to_string())];
+        for (i, c) in f.cars.iter().enumerate() {
+            s.push((format!("cars[{}].model", i), format!("{:?}", c.model)));
+            for (name, _, value) in c.fields() {
+                s.push((format!("cars[{}].{}", i, name), value.to_string()));
+            }
+        }
+        s
+    }
+    fn write(&self, data: &[u8]) -> Option<Result<Vec<u8>, Box<dyn error::Error>>> {
+        let res = read(&mut Cursor::new(data)).and_then(|x| {
+            let mut out = vec![];
+            write(&mut out, &x)?;
+            Ok(out)
+        });
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+
+    // two cars with a tail after a name, a NaN, an infinity and extra words
+    fn vanilla() -> Vec<u8> {
+        let mut data = vec![];
+        data.write_i32::<LittleEndian>(common::FORMAT_VERSION)
+            .unwrap();
+        data.write_i32::<LittleEndian>(2).unwrap();
+        for (i, (model, tail)) in [("car1.box", &b"junk"[..]), ("car2.box", &[])]
+            .iter()
+            .enumerate()
+        {
+            common::write_fixed_string(&mut data, model, tail, common::NAME_LEN).unwrap();
+            for v in [1.1, f32::NAN, -3.25, f32::INFINITY] {
+                data.write_f32::<LittleEndian>(v * (i + 1) as f32).unwrap();
+            }
+            data.write_u32::<LittleEndian>(0x7fc00001).unwrap();
+            data.write_u32::<LittleEndian>(5).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let dir = std::env::temp_dir().join(format!("carprop_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("carproperty.dat");
+        fs::write(&input, vanilla()).unwrap();
+        for ext in ["toml", "json"] {
+            let text = dir.join(format!("carproperty.{}", ext));
+            let back = dir.join(format!("back_{}.dat", ext));
+            export(&input, Some(text.to_str().unwrap().to_owned())).unwrap();
+            import(&text, Some(back.to_str().unwrap().to_owned())).unwrap();
+            assert_eq!(fs::read(&back).unwrap(), vanilla());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,20 +1,25 @@
 use std::{
-    fs,
-    io::{Read, Seek, SeekFrom},
-    path::{Path, PathBuf},
+    error,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
 };
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use crate::{common, conversion::obj};
+use crate::{
+    common,
+    formats::obj::{self, read_vec3, write_vec3, Geometry, Material},
+    mesh::{Marker, Mesh},
+    registry::AssetHandler,
+};
 
-/*struct ObjPos {
-    vec: (f32, f32, f32),
-    angle: f32,
-    type_: i32,
+pub struct ObjPos {
+    pub vec: (f32, f32, f32),
+    // the original code negates this on load, it's kept as stored here
+    pub angle: f32,
+    pub type_: i32,
 }
 
-struct Cell {
+/*struct Cell {
     numcells: i32,
     numtri: i32,
     numuv: i32,
@@ -43,225 +48,121 @@ struct TexAnimation {
     uv_index_array: Vec<u16>,
 }*/
 
-pub fn map_to_obj<R: Read + Seek>(
-    mut f: R,
-    input: &Path,
-    output: &Path,
-    root: Option<String>,
-) {
-    //
-    // ReadHeader
-    //
-    let magic = f.read_i32::<LittleEndian>().unwrap();
-    let version = f.read_i32::<LittleEndian>().unwrap();
+pub struct MapHeader {
+    pub geom_off: i32,
+    pub split: i32,
+    pub vtx_num: i32,
+    pub uv_num: i32,
+    pub tri_num: i32,
+    pub max: (f32, f32, f32),
+    pub min: (f32, f32, f32),
+    pub mat_num: i32,
+    pub mat_off: i32,
+    pub height_off: i32,
+    pub obj_pos_num: i32,
+    pub obj_pos_off: i32,
+    pub cell_num: i32,
+    pub cell_off: i32,
+    pub grid_max: (f32, f32, f32),
+    pub grid_min: (f32, f32, f32),
+    pub uv_anim_num: i32,
+    pub tex_anim_num: i32,
+    pub uv_anim_off: i32,
+    pub tex_anim_off: i32,
+}
 
-    if magic != 1245859584 || version != 16777216 {
-        eprintln!("Invalid magic or version, skipping...");
-        return;
-    }
+pub struct MapFile {
+    pub header: MapHeader,
+    pub geometry: Geometry,
+    pub materials: Vec<Material>,
+    pub obj_pos: Vec<ObjPos>,
+}
 
-    let geom_off = f.read_i32::<LittleEndian>().unwrap();
-    f.read_i32::<LittleEndian>().unwrap(); // unused, split
-    let vtx_num = f.read_i32::<LittleEndian>().unwrap();
-    let uv_num = f.read_i32::<LittleEndian>().unwrap();
-    let tri_num = f.read_i32::<LittleEndian>().unwrap();
-
-    // unused for conversion atm
-    let _max = (
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-    );
-    let _min = (
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-    );
-
-    let mat_num = f.read_i32::<LittleEndian>().unwrap();
-    let mat_off = f.read_i32::<LittleEndian>().unwrap();
-    let mut mat_off_cur = mat_off as u64;
-
-    f.read_i32::<LittleEndian>().unwrap(); // unused, height_off
-
-    f.read_i32::<LittleEndian>().unwrap(); // unused, obj_pos_num
-    f.read_i32::<LittleEndian>().unwrap(); // unused, obj_pos_off
-
-    f.read_i32::<LittleEndian>().unwrap(); // unused, cell_num
-    f.read_i32::<LittleEndian>().unwrap(); // unused, cell_off
-
-    // unused for conversion atm
-    let _grid_max = (
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-    );
-    let _grid_min = (
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-    );
-
-    f.read_i32::<LittleEndian>().unwrap(); // unused, uv_anim_num
-    f.read_i32::<LittleEndian>().unwrap(); // unused, tex_anim_num
-    f.read_i32::<LittleEndian>().unwrap(); // unused, uv_anim_off
-    f.read_i32::<LittleEndian>().unwrap(); // unused, tex_anim_off
-    /*let mut uv_anim_off_cur = uv_anim_off as u64;
-    let mut tex_anim_off_cur = tex_anim_off as u64;*/
+// vec, angle, type
+pub const OBJ_POS_LEN: u64 = 20;
 
-    //
-    // ReadGeomData
-    //
-    f.seek(SeekFrom::Start(geom_off as u64)).unwrap();
-
-    let mut vtx_buf = vec![];
-    for _ in 0..vtx_num {
-        vtx_buf.push((
-            f.read_f32::<LittleEndian>().unwrap(),
-            f.read_f32::<LittleEndian>().unwrap(),
-            f.read_f32::<LittleEndian>().unwrap(),
-        ));
-    }
+pub fn read<R: Read + Seek>(f: &mut R) -> Result<MapFile, Box<dyn error::Error>> {
+    read_map(f, true)
+}
 
-    let mut tri_buf = vec![];
-    for _ in 0..tri_num {
-        tri_buf.push((
-            f.read_u16::<LittleEndian>().unwrap(),
-            f.read_u16::<LittleEndian>().unwrap(),
-            f.read_u16::<LittleEndian>().unwrap(),
-        ));
-    }
+// the object positions are only markers when converting, so a broken table there
+// is warned about and left out instead of failing the whole level
+pub fn read_for_mesh<R: Read + Seek>(f: &mut R) -> Result<MapFile, Box<dyn error::Error>> {
+    read_map(f, false)
+}
 
-    let mut uv_buf = vec![];
-    for _ in 0..uv_num {
-        uv_buf.push((
-            f.read_f32::<LittleEndian>().unwrap(),
-            1.0 - f.read_f32::<LittleEndian>().unwrap(),
-        ));
+fn read_obj_pos<R: Read + Seek>(
+    f: &mut R,
+    header: &MapHeader,
+) -> Result<Vec<ObjPos>, Box<dyn error::Error>> {
+    f.seek(SeekFrom::Start(header.obj_pos_off as u64))?;
+    let mut obj_pos = vec![];
+    for _ in 0..header.obj_pos_num {
+        obj_pos.push(ObjPos {
+            vec: read_vec3(f)?,
+            angle: f.read_f32::<LittleEndian>()?,
+            type_: f.read_i32::<LittleEndian>()?,
+        });
     }
+    Ok(obj_pos)
+}
 
+fn read_map<R: Read + Seek>(f: &mut R, strict: bool) -> Result<MapFile, Box<dyn error::Error>> {
     //
-    // ReadMaterials
+    // ReadHeader
     //
-    for i in 0..mat_num {
-        f.seek(SeekFrom::Start(mat_off_cur)).unwrap();
-
-        let poly_off = f.read_i32::<LittleEndian>().unwrap();
-        let poly_num = f.read_i32::<LittleEndian>().unwrap();
-        f.read_i32::<LittleEndian>().unwrap(); // unknown and unused
-        
-        // due to this string being static size i need to trim excess NULLs at the end
-        let tex_name = common::read_string(&mut f, 100)
-            .unwrap()
-            .trim_end_matches('\0')
-            .to_owned();
-
-        // i guess we just throw these away?
-        f.read_u16::<LittleEndian>().unwrap();
-        f.read_u16::<LittleEndian>().unwrap();
-
-        mat_off_cur = f.seek(SeekFrom::Current(0)).unwrap();
-
-        // ReadPolyData
-        f.seek(SeekFrom::Start(poly_off as u64)).unwrap();
-
-        let mut thing_buf = vec![];
-        for _ in 0..poly_num {
-            let pos = f.read_u16::<LittleEndian>().unwrap() as usize;
-            thing_buf.push(tri_buf[pos]);
-        }
-
-        // logging stuff
-        let pfx = if mat_num > 1 {
-            format!("[{}/{}] ", i + 1, mat_num)
-        } else {
-            String::new()
-        };
-
-        // path stuff time!!!!
-        let in_base = input.file_name().unwrap().to_str().unwrap();
-
-        let obj_f_name = {
-            if mat_num > 1 {
-                format!("{}.{}.obj", in_base, i)
-            } else {
-                format!("{}.obj", in_base)
-            }
-        };
-        let mtl_f_name = {
-            if mat_num > 1 {
-                format!("{}.{}.mtl", in_base, i)
-            } else {
-                format!("{}.mtl", in_base)
-            }
-        };
-
-        // check if outputs already exist
-        let obj_f_path = output.join(obj_f_name);
-        if obj_f_path.try_exists().unwrap() {
-            eprintln!("Output file {:?} already exists, exiting...", obj_f_path);
-            return;
-        }
-        let mtl_f_path = output.join(&mtl_f_name);
-        if mtl_f_path.try_exists().unwrap() {
-            eprintln!("Output file {:?} already exists, exiting...", mtl_f_path);
-            return;
-        }
+    obj::check_magic(f)?;
+
+    let header = MapHeader {
+        geom_off: f.read_i32::<LittleEndian>()?,
+        split: f.read_i32::<LittleEndian>()?,
+        vtx_num: f.read_i32::<LittleEndian>()?,
+        uv_num: f.read_i32::<LittleEndian>()?,
+        tri_num: f.read_i32::<LittleEndian>()?,
+        max: read_vec3(f)?,
+        min: read_vec3(f)?,
+        mat_num: f.read_i32::<LittleEndian>()?,
+        mat_off: f.read_i32::<LittleEndian>()?,
+        height_off: f.read_i32::<LittleEndian>()?,
+        obj_pos_num: f.read_i32::<LittleEndian>()?,
+        obj_pos_off: f.read_i32::<LittleEndian>()?,
+        cell_num: f.read_i32::<LittleEndian>()?,
+        cell_off: f.read_i32::<LittleEndian>()?,
+        grid_max: read_vec3(f)?,
+        grid_min: read_vec3(f)?,
+        uv_anim_num: f.read_i32::<LittleEndian>()?,
+        tex_anim_num: f.read_i32::<LittleEndian>()?,
+        uv_anim_off: f.read_i32::<LittleEndian>()?,
+        tex_anim_off: f.read_i32::<LittleEndian>()?,
+    };
+
+    let geometry = obj::read_geometry(
+        f,
+        header.geom_off,
+        header.vtx_num,
+        header.uv_num,
+        header.tri_num,
+    )?;
+    let materials = obj::read_materials(f, header.mat_off, header.mat_num)?;
 
-        // write object
-        println!("{}Writing object (.obj) to {:?}...", pfx, obj_f_path);
-        let mut obj_f = fs::File::create(obj_f_path).unwrap();
-        obj::write_obj_alt(&mut obj_f, &mtl_f_name, &vtx_buf, &uv_buf, &thing_buf).unwrap();
-
-        // then write material
-        println!("{}Writing material (.mtl) to {:?}...", pfx, mtl_f_path);
-        let mut mtl_f = fs::File::create(mtl_f_path).unwrap();
-        obj::write_mtl(&mut mtl_f, &tex_name).unwrap();
-
-        // check if we can auto copy texture
-        let tex_base = Path::new(&tex_name).file_name().unwrap().to_str().unwrap();
-        let tex_path = match root {
-            Some(ref v) => Path::new(v).join(&tex_name),
-            None => PathBuf::from(&tex_name),
-        };
-        if tex_path.is_file() {
-            // check if we already did
-            let dest = output.join(&tex_base);
-            if dest.try_exists().unwrap() && dest.is_file() {
-                println!("{}Skipping copying texture, already exists", pfx);
-            } else {
-                println!("{}Copying texture to {:?}...", pfx, dest);
-                fs::copy(tex_path, dest).unwrap();
-            }
-        } else {
-            // otherwise texture is manual because we don't know where it is
-            println!(
-                "{}Couldn't find texture, copy texture from \"{}\" to view with textures",
-                pfx, tex_name
+    //
+    // ReadObjPosInfo
+    //
+    let obj_pos = match read_obj_pos(f, &header) {
+        Ok(v) => v,
+        Err(e) if !strict => {
+            eprintln!(
+                "Warning: couldn't read the object positions: {}, leaving them out",
+                e
             );
+            vec![]
         }
-    }
+        Err(e) => return Err(format!("Couldn't read the object positions: {}", e).into()),
+    };
 
     // Everything under here is unused atm
 
     /*
-    //
-    // ReadObjPosInfo
-    //
-    f.seek(SeekFrom::Start(obj_pos_off as u64)).unwrap();
-    let mut obj_pos_arr = vec![];
-    for _ in 0..obj_pos_num {
-        obj_pos_arr.push(ObjPos {
-            vec: (
-                f.read_f32::<LittleEndian>().unwrap(),
-                f.read_f32::<LittleEndian>().unwrap(),
-                f.read_f32::<LittleEndian>().unwrap(),
-            ),
-            angle: -f.read_f32::<LittleEndian>().unwrap(),
-            type_: f.read_i32::<LittleEndian>().unwrap(),
-        });
-    }
-
     //
     // ReadPVS
     //
@@ -320,7 +221,7 @@ pub fn map_to_obj<R: Read + Seek>(
         let flow_v = f.read_f32::<LittleEndian>().unwrap();
         let uv_index_num = f.read_i32::<LittleEndian>().unwrap();
         let ofs = f.read_i32::<LittleEndian>().unwrap();
-        uv_anim_off_cur = f.seek(SeekFrom::Current(0)).unwrap();
+        uv_anim_off_cur = f.stream_position().unwrap();
 
         //
         // ReadUVIndexInfo
@@ -347,7 +248,7 @@ pub fn map_to_obj<R: Read + Seek>(
         let type_ = f.read_i32::<LittleEndian>().unwrap();
         let ofs = f.read_i32::<LittleEndian>().unwrap();
         let uv_index_num = f.read_i32::<LittleEndian>().unwrap();
-        tex_anim_off_cur = f.seek(SeekFrom::Current(0)).unwrap();
+        tex_anim_off_cur = f.stream_position().unwrap();
 
         //
         // ReadTextureUVIndexInfo
@@ -364,4 +265,117 @@ pub fn map_to_obj<R: Read + Seek>(
         });
     }
     */
+
+    Ok(MapFile {
+        header,
+        geometry,
+        materials,
+        obj_pos,
+    })
+}
+
+// see obj::write, cells/animations/height data aren't parsed so write over a copy
+// of the original file to keep them
+pub fn write<W: Write + Seek>(f: &mut W, map_file: &MapFile) -> Result<(), Box<dyn error::Error>> {
+    let h = &map_file.header;
+    obj::check_counts(
+        h.vtx_num,
+        h.uv_num,
+        h.tri_num,
+        h.mat_num,
+        &map_file.geometry,
+        &map_file.materials,
+    )?;
+    if h.obj_pos_num as usize != map_file.obj_pos.len() {
+        return Err("Header counts don't match the data".into());
+    }
+
+    f.seek(SeekFrom::Start(0))?;
+    f.write_i32::<LittleEndian>(obj::MAGIC)?;
+    f.write_i32::<LittleEndian>(common::FORMAT_VERSION)?;
+    f.write_i32::<LittleEndian>(h.geom_off)?;
+    f.write_i32::<LittleEndian>(h.split)?;
+    f.write_i32::<LittleEndian>(h.vtx_num)?;
+    f.write_i32::<LittleEndian>(h.uv_num)?;
+    f.write_i32::<LittleEndian>(h.tri_num)?;
+    write_vec3(f, h.max)?;
+    write_vec3(f, h.min)?;
+    f.write_i32::<LittleEndian>(h.mat_num)?;
+    f.write_i32::<LittleEndian>(h.mat_off)?;
+    f.write_i32::<LittleEndian>(h.height_off)?;
+    f.write_i32::<LittleEndian>(h.obj_pos_num)?;
+    f.write_i32::<LittleEndian>(h.obj_pos_off)?;
+    f.write_i32::<LittleEndian>(h.cell_num)?;
+    f.write_i32::<LittleEndian>(h.cell_off)?;
+    write_vec3(f, h.grid_max)?;
+    write_vec3(f, h.grid_min)?;
+    f.write_i32::<LittleEndian>(h.uv_anim_num)?;
+    f.write_i32::<LittleEndian>(h.tex_anim_num)?;
+    f.write_i32::<LittleEndian>(h.uv_anim_off)?;
+    f.write_i32::<LittleEndian>(h.tex_anim_off)?;
+
+    obj::write_geometry(f, h.geom_off, &map_file.geometry)?;
+    obj::write_materials(f, h.mat_off, &map_file.materials)?;
+
+    f.seek(SeekFrom::Start(h.obj_pos_off as u64))?;
+    for x in &map_file.obj_pos {
+        write_vec3(f, x.vec)?;
+        f.write_f32::<LittleEndian>(x.angle)?;
+        f.write_i32::<LittleEndian>(x.type_)?;
+    }
+    f.flush()?;
+    Ok(())
+}
+
+// the level geometry with the object positions as markers, what the type and angle
+// mean isn't known so they only go along as hints
+pub fn to_mesh(map_file: &MapFile) -> Result<Mesh, Box<dyn error::Error>> {
+    let mut mesh = obj::to_mesh(&map_file.geometry, &map_file.materials)?;
+    for (i, x) in map_file.obj_pos.iter().enumerate() {
+        mesh.markers.push(Marker {
+            name: format!("obj_pos_{}", i),
+            position: x.vec,
+            hints: vec![format!("type {}", x.type_), format!("angle {}", x.angle)],
+        });
+    }
+    Ok(mesh)
+}
+
+pub struct MapHandler;
+
+impl AssetHandler for MapHandler {
+    fn type_id(&self) -> u8 {
+        2
+    }
+    fn name(&self) -> &'static str {
+        "map"
+    }
+    fn extensions(&self) -> &'static [&'static str] {
+        &["map"]
+    }
+    fn parse(&self, data: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        let x = read(&mut Cursor::new(data))?;
+        obj::to_mesh(&x.geometry, &x.materials)?;
+        Ok(())
+    }
+    fn limits(&self, data: &[u8]) -> Vec<String> {
+        match read(&mut Cursor::new(data)) {
+            Ok(x) => obj::limit_problems(&x.geometry, &x.materials),
+            Err(_) => vec![],
+        }
+    }
+    fn to_mesh(&self, data: &[u8]) -> Option<Result<Mesh, Box<dyn error::Error>>> {
+        Some(read_for_mesh(&mut Cursor::new(data)).and_then(|x| to_mesh(&x)))
+    }
+    fn export_targets(&self) -> &'static [&'static str] {
+        &["obj", "gltf"]
+    }
+    fn write(&self, data: &[u8]) -> Option<Result<Vec<u8>, Box<dyn error::Error>>> {
+        let res = read(&mut Cursor::new(data)).and_then(|x| {
+            let mut out = Cursor::new(data.to_vec());
+            write(&mut out, &x)?;
+            Ok(out.into_inner())
+        });
+        Some(res)
+    }
 }
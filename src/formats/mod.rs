@@ -1,3 +1,5 @@
+pub mod animate;
 pub mod r#box;
+pub mod carproperty;
 pub mod map;
-pub mod obj;
\ No newline at end of file
+pub mod obj;
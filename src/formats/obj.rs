@@ -1,178 +1,506 @@
 use std::{
-    fs,
-    io::{Read, Seek, SeekFrom},
+    error, fs,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
 };
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use crate::{common, conversion::obj};
+use crate::{
+    common,
+    formats::map,
+    mesh::{self, Corner, Face, Mesh},
+    output::{OutputSet, Policy},
+    registry::AssetHandler,
+};
 
-pub fn obj_to_obj<R: Read + Seek>(mut f: R, input: &Path, output: &Path, root: Option<String>) {
-    //
-    // ReadHeader
-    //
-    let magic = f.read_i32::<LittleEndian>().unwrap();
-    let version = f.read_i32::<LittleEndian>().unwrap();
-
-    if magic != 1245859584 || version != 16777216 {
-        eprintln!("Invalid magic or version, exiting...");
-        return;
-    }
-
-    let geom_off = f.read_i32::<LittleEndian>().unwrap();
-    let vtx_num = f.read_i32::<LittleEndian>().unwrap();
-    let uv_num = f.read_i32::<LittleEndian>().unwrap();
-    let tri_num = f.read_i32::<LittleEndian>().unwrap();
-
-    // unused for conversion atm
-    let _max = (
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-    );
-    let _min = (
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-        f.read_f32::<LittleEndian>().unwrap(),
-    );
-
-    let mat_num = f.read_i32::<LittleEndian>().unwrap();
-    let mat_off = f.read_i32::<LittleEndian>().unwrap();
-    let mut mat_off_cur = mat_off as u64;
+// shared with .map
+pub const MAGIC: i32 = 1245859584;
 
-    //
-    // ReadGeomData
-    //
-    f.seek(SeekFrom::Start(geom_off as u64)).unwrap();
+pub struct ObjHeader {
+    pub geom_off: i32,
+    pub vtx_num: i32,
+    pub uv_num: i32,
+    pub tri_num: i32,
+    pub max: (f32, f32, f32),
+    pub min: (f32, f32, f32),
+    pub mat_num: i32,
+    pub mat_off: i32,
+}
+
+// vertices and uvs share indices, so there is one uv per vertex
+pub struct Geometry {
+    pub vertices: Vec<(f32, f32, f32)>,
+    pub tris: Vec<(u16, u16, u16)>,
+    pub uvs: Vec<(f32, f32)>,
+}
 
-    let mut vtx_buf = vec![];
+// the unknown fields look like render flags (blend mode, double sided, alpha test?)
+// but nothing is confirmed yet, so they are only carried along
+pub struct Material {
+    pub poly_off: i32,
+    pub poly_num: i32,
+    pub unknown: i32,
+    pub tex_name: String,
+    pub tex_tail: Vec<u8>,
+    pub unknown_a: u16,
+    pub unknown_b: u16,
+    // indices into Geometry::tris
+    pub polys: Vec<u16>,
+}
+
+pub struct ObjFile {
+    pub header: ObjHeader,
+    pub geometry: Geometry,
+    pub materials: Vec<Material>,
+}
+
+// poly_off, poly_num, unknown, tex_name, unknown_a, unknown_b
+pub const MATERIAL_LEN: u64 = 12 + common::NAME_LEN as u64 + 4;
+
+pub fn read_vec3<R: Read>(f: &mut R) -> Result<(f32, f32, f32), Box<dyn error::Error>> {
+    Ok((
+        f.read_f32::<LittleEndian>()?,
+        f.read_f32::<LittleEndian>()?,
+        f.read_f32::<LittleEndian>()?,
+    ))
+}
+
+pub fn check_magic<R: Read>(f: &mut R) -> Result<(), Box<dyn error::Error>> {
+    let magic = f.read_i32::<LittleEndian>()?;
+    let version = f.read_i32::<LittleEndian>()?;
+    if magic != MAGIC || version != common::FORMAT_VERSION {
+        return Err("Invalid magic or version".into());
+    }
+    Ok(())
+}
+
+//
+// ReadGeomData
+//
+pub fn read_geometry<R: Read + Seek>(
+    f: &mut R,
+    geom_off: i32,
+    vtx_num: i32,
+    uv_num: i32,
+    tri_num: i32,
+) -> Result<Geometry, Box<dyn error::Error>> {
+    if vtx_num < 0 || uv_num < 0 || tri_num < 0 {
+        return Err("Negative vertex, uv or triangle count".into());
+    }
+    f.seek(SeekFrom::Start(geom_off as u64))?;
+
+    let mut vertices = vec![];
     for _ in 0..vtx_num {
-        vtx_buf.push((
-            f.read_f32::<LittleEndian>().unwrap(),
-            f.read_f32::<LittleEndian>().unwrap(),
-            f.read_f32::<LittleEndian>().unwrap(),
-        ));
+        vertices.push(read_vec3(f)?);
     }
 
-    let mut tri_buf = vec![];
+    let mut tris = vec![];
     for _ in 0..tri_num {
-        tri_buf.push((
-            f.read_u16::<LittleEndian>().unwrap(),
-            f.read_u16::<LittleEndian>().unwrap(),
-            f.read_u16::<LittleEndian>().unwrap(),
+        tris.push((
+            f.read_u16::<LittleEndian>()?,
+            f.read_u16::<LittleEndian>()?,
+            f.read_u16::<LittleEndian>()?,
         ));
     }
 
-    let mut uv_buf = vec![];
+    let mut uvs = vec![];
     for _ in 0..uv_num {
-        uv_buf.push((
-            f.read_f32::<LittleEndian>().unwrap(),
-            1.0 - f.read_f32::<LittleEndian>().unwrap(),
-        ));
+        uvs.push((f.read_f32::<LittleEndian>()?, f.read_f32::<LittleEndian>()?));
     }
 
-    //
-    // ReadMaterials
-    //
-    for i in 0..mat_num {
-        f.seek(SeekFrom::Start(mat_off_cur)).unwrap();
-
-        let poly_off = f.read_i32::<LittleEndian>().unwrap();
-        let poly_num = f.read_i32::<LittleEndian>().unwrap();
-        f.read_i32::<LittleEndian>().unwrap(); // unknown and unused
+    Ok(Geometry {
+        vertices,
+        tris,
+        uvs,
+    })
+}
 
-        // due to this string being static size i need to trim excess NULLs at the end
-        let tex_name = common::read_string(&mut f, 100)
-            .unwrap()
-            .trim_end_matches('\0')
-            .to_owned();
+//
+// ReadMaterials
+//
+pub fn read_materials<R: Read + Seek>(
+    f: &mut R,
+    mat_off: i32,
+    mat_num: i32,
+) -> Result<Vec<Material>, Box<dyn error::Error>> {
+    let mut mat_off_cur = mat_off as u64;
+    let mut materials = vec![];
+    for _ in 0..mat_num {
+        f.seek(SeekFrom::Start(mat_off_cur))?;
 
-        // i guess we just throw these away?
-        f.read_u16::<LittleEndian>().unwrap();
-        f.read_u16::<LittleEndian>().unwrap();
+        let poly_off = f.read_i32::<LittleEndian>()?;
+        let poly_num = f.read_i32::<LittleEndian>()?;
+        let unknown = f.read_i32::<LittleEndian>()?;
+        let (tex_name, tex_tail) = common::read_fixed_string(f, common::NAME_LEN)?;
+        let unknown_a = f.read_u16::<LittleEndian>()?;
+        let unknown_b = f.read_u16::<LittleEndian>()?;
 
-        mat_off_cur = f.seek(SeekFrom::Current(0)).unwrap();
+        mat_off_cur = f.stream_position()?;
 
         //
         // ReadPolyData
         //
-        f.seek(SeekFrom::Start(poly_off as u64)).unwrap();
+        f.seek(SeekFrom::Start(poly_off as u64))?;
 
-        let mut thing_buf = vec![];
+        let mut polys = vec![];
         for _ in 0..poly_num {
-            let pos = f.read_u16::<LittleEndian>().unwrap() as usize;
-            thing_buf.push(tri_buf[pos]);
+            polys.push(f.read_u16::<LittleEndian>()?);
         }
 
-        // logging stuff
-        let pfx = if mat_num > 1 {
-            format!("[{}/{}] ", i + 1, mat_num)
-        } else {
-            String::new()
-        };
-
-        // path stuff time!!!!
-        let in_base = input.file_name().unwrap().to_str().unwrap();
-
-        let obj_f_name = {
-            if mat_num > 1 {
-                format!("{}.{}.obj", in_base, i)
-            } else {
-                format!("{}.obj", in_base)
-            }
-        };
-        let mtl_f_name = {
-            if mat_num > 1 {
-                format!("{}.{}.mtl", in_base, i)
-            } else {
-                format!("{}.mtl", in_base)
-            }
-        };
-
-        // check if outputs already exist
-        let obj_f_path = output.join(obj_f_name);
-        if obj_f_path.try_exists().unwrap() {
-            eprintln!("Output file {:?} already exists, exiting...", obj_f_path);
-            return;
+        materials.push(Material {
+            poly_off,
+            poly_num,
+            unknown,
+            tex_name,
+            tex_tail,
+            unknown_a,
+            unknown_b,
+            polys,
+        });
+    }
+    Ok(materials)
+}
+
+pub fn read<R: Read + Seek>(f: &mut R) -> Result<ObjFile, Box<dyn error::Error>> {
+    //
+    // ReadHeader
+    //
+    check_magic(f)?;
+
+    let header = ObjHeader {
+        geom_off: f.read_i32::<LittleEndian>()?,
+        vtx_num: f.read_i32::<LittleEndian>()?,
+        uv_num: f.read_i32::<LittleEndian>()?,
+        tri_num: f.read_i32::<LittleEndian>()?,
+        max: read_vec3(f)?,
+        min: read_vec3(f)?,
+        mat_num: f.read_i32::<LittleEndian>()?,
+        mat_off: f.read_i32::<LittleEndian>()?,
+    };
+
+    let geometry = read_geometry(
+        f,
+        header.geom_off,
+        header.vtx_num,
+        header.uv_num,
+        header.tri_num,
+    )?;
+    let materials = read_materials(f, header.mat_off, header.mat_num)?;
+
+    Ok(ObjFile {
+        header,
+        geometry,
+        materials,
+    })
+}
+
+// Writers put every part back at the offset it was read from, so the layout stays
+// exactly the same. Anything we don't parse (map cells etc.) is left alone, so write
+// over a copy of the original file to keep it.
+
+pub fn write_vec3<W: Write>(f: &mut W, v: (f32, f32, f32)) -> Result<(), Box<dyn error::Error>> {
+    f.write_f32::<LittleEndian>(v.0)?;
+    f.write_f32::<LittleEndian>(v.1)?;
+    f.write_f32::<LittleEndian>(v.2)?;
+    Ok(())
+}
+
+pub fn check_counts(
+    vtx_num: i32,
+    uv_num: i32,
+    tri_num: i32,
+    mat_num: i32,
+    geometry: &Geometry,
+    materials: &[Material],
+) -> Result<(), Box<dyn error::Error>> {
+    if vtx_num as usize != geometry.vertices.len()
+        || uv_num as usize != geometry.uvs.len()
+        || tri_num as usize != geometry.tris.len()
+        || mat_num as usize != materials.len()
+        || materials
+            .iter()
+            .any(|x| x.poly_num as usize != x.polys.len())
+    {
+        return Err("Header counts don't match the data".into());
+    }
+    Ok(())
+}
+
+pub fn write_geometry<W: Write + Seek>(
+    f: &mut W,
+    geom_off: i32,
+    geometry: &Geometry,
+) -> Result<(), Box<dyn error::Error>> {
+    f.seek(SeekFrom::Start(geom_off as u64))?;
+    for x in &geometry.vertices {
+        write_vec3(f, *x)?;
+    }
+    for x in &geometry.tris {
+        f.write_u16::<LittleEndian>(x.0)?;
+        f.write_u16::<LittleEndian>(x.1)?;
+        f.write_u16::<LittleEndian>(x.2)?;
+    }
+    for x in &geometry.uvs {
+        f.write_f32::<LittleEndian>(x.0)?;
+        f.write_f32::<LittleEndian>(x.1)?;
+    }
+    Ok(())
+}
+
+pub fn write_materials<W: Write + Seek>(
+    f: &mut W,
+    mat_off: i32,
+    materials: &[Material],
+) -> Result<(), Box<dyn error::Error>> {
+    f.seek(SeekFrom::Start(mat_off as u64))?;
+    for x in materials {
+        f.write_i32::<LittleEndian>(x.poly_off)?;
+        f.write_i32::<LittleEndian>(x.poly_num)?;
+        f.write_i32::<LittleEndian>(x.unknown)?;
+        common::write_fixed_string(f, &x.tex_name, &x.tex_tail, common::NAME_LEN)?;
+        f.write_u16::<LittleEndian>(x.unknown_a)?;
+        f.write_u16::<LittleEndian>(x.unknown_b)?;
+    }
+    for x in materials {
+        f.seek(SeekFrom::Start(x.poly_off as u64))?;
+        for p in &x.polys {
+            f.write_u16::<LittleEndian>(*p)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write<W: Write + Seek>(f: &mut W, obj_file: &ObjFile) -> Result<(), Box<dyn error::Error>> {
+    let h = &obj_file.header;
+    check_counts(
+        h.vtx_num,
+        h.uv_num,
+        h.tri_num,
+        h.mat_num,
+        &obj_file.geometry,
+        &obj_file.materials,
+    )?;
+
+    f.seek(SeekFrom::Start(0))?;
+    f.write_i32::<LittleEndian>(MAGIC)?;
+    f.write_i32::<LittleEndian>(common::FORMAT_VERSION)?;
+    f.write_i32::<LittleEndian>(h.geom_off)?;
+    f.write_i32::<LittleEndian>(h.vtx_num)?;
+    f.write_i32::<LittleEndian>(h.uv_num)?;
+    f.write_i32::<LittleEndian>(h.tri_num)?;
+    write_vec3(f, h.max)?;
+    write_vec3(f, h.min)?;
+    f.write_i32::<LittleEndian>(h.mat_num)?;
+    f.write_i32::<LittleEndian>(h.mat_off)?;
+
+    write_geometry(f, h.geom_off, &obj_file.geometry)?;
+    write_materials(f, h.mat_off, &obj_file.materials)?;
+    f.flush()?;
+    Ok(())
+}
+
+// triangles point at vertices and materials at triangles with u16 indices, so
+// anything past that can't be used
+pub const MAX_INDEXED: usize = u16::MAX as usize + 1;
+
+// shared with .map
+pub fn limit_problems(geometry: &Geometry, materials: &[Material]) -> Vec<String> {
+    let mut problems = vec![];
+    if geometry.vertices.len() > MAX_INDEXED {
+        problems.push(format!(
+            "{} vertices, triangles can only use the first {}",
+            geometry.vertices.len(),
+            MAX_INDEXED
+        ));
+    }
+    if geometry.tris.len() > MAX_INDEXED {
+        problems.push(format!(
+            "{} triangles, materials can only use the first {}",
+            geometry.tris.len(),
+            MAX_INDEXED
+        ));
+    }
+    for x in materials {
+        problems.extend(common::fixed_string_problem(
+            "texture",
+            &x.tex_name,
+            common::NAME_LEN,
+        ));
+    }
+    problems
+}
+
+// the unknown fields go into the .mtl as comments, so they are at least visible
+// next to the texture when figuring out what they do. nobody knows yet, so they're
+// only the raw values
+pub fn material_hints(mat: &Material) -> Vec<String> {
+    vec![format!(
+        "unknown {}, unknown_a {}, unknown_b {} (raw values, meaning unconfirmed)",
+        mat.unknown, mat.unknown_a, mat.unknown_b
+    )]
+}
+
+// new values for the unknown fields of one material, None keeps what's there
+pub struct MaterialEdit {
+    pub index: Option<usize>,
+    pub unknown: Option<i32>,
+    pub unknown_a: Option<u16>,
+    pub unknown_b: Option<u16>,
+}
+
+// shows the materials of an obj/map and optionally changes the unknown fields,
+// so it's possible to poke at them and see what changes in game. the change goes
+// to <stem>.edited.<ext> unless it's asked to go somewhere else or in place
+pub fn edit_material(
+    input: &Path,
+    output: Option<String>,
+    in_place: bool,
+    policy: Policy,
+    edit: MaterialEdit,
+) -> Result<(), Box<dyn error::Error>> {
+    let data = fs::read(input)?;
+    let is_map = input
+        .extension()
+        .map(|x| x.eq_ignore_ascii_case("map"))
+        .unwrap_or(false);
+
+    enum Model {
+        Obj(ObjFile),
+        Map(map::MapFile),
+    }
+    let mut c = Cursor::new(&data);
+    let parsed = if is_map {
+        map::read(&mut c).map(Model::Map)
+    } else {
+        read(&mut c).map(Model::Obj)
+    };
+    let mut model = parsed.map_err(|e| format!("Couldn't read {:?}: {}", input, e))?;
+    let materials = match &mut model {
+        Model::Obj(v) => &mut v.materials,
+        Model::Map(v) => &mut v.materials,
+    };
+
+    for (i, x) in materials.iter().enumerate() {
+        println!("[{}] \"{}\", {}", i, x.tex_name, material_hints(x)[0]);
+    }
+
+    if edit.unknown.is_none() && edit.unknown_a.is_none() && edit.unknown_b.is_none() {
+        return Ok(());
+    }
+    let mat = edit
+        .index
+        .and_then(|i| materials.get_mut(i))
+        .ok_or("Pick an existing material to change")?;
+    mat.unknown = edit.unknown.unwrap_or(mat.unknown);
+    mat.unknown_a = edit.unknown_a.unwrap_or(mat.unknown_a);
+    mat.unknown_b = edit.unknown_b.unwrap_or(mat.unknown_b);
+    println!("Changed to {}", material_hints(mat)[0]);
+
+    // changing the input was asked for, so it's always written
+    let (out_path, policy) = match output {
+        Some(v) => (PathBuf::from(v), policy),
+        None if in_place => (input.to_owned(), Policy::Force),
+        None => {
+            let stem = input.file_stem().unwrap().to_string_lossy();
+            let name = match input.extension() {
+                Some(ext) => format!("{}.edited.{}", stem, ext.to_string_lossy()),
+                None => format!("{}.edited", stem),
+            };
+            (input.with_file_name(name), policy)
+        }
+    };
+
+    // write over a copy so the parts we don't parse stay put
+    let mut buf = Cursor::new(data.clone());
+    match &model {
+        Model::Obj(v) => write(&mut buf, v)?,
+        Model::Map(v) => map::write(&mut buf, v)?,
+    }
+    let mut out = OutputSet::default();
+    out.add(out_path, buf.into_inner());
+    out.commit(policy)?;
+    Ok(())
+}
+
+// obj and map only differ in the header, so they share this. triangles that no
+// material points at aren't drawn by the game, so they're left out
+pub fn to_mesh(geometry: &Geometry, materials: &[Material]) -> Result<Mesh, Box<dyn error::Error>> {
+    // vertices and uvs share indices
+    let corner = |v: u16| -> Result<Corner, Box<dyn error::Error>> {
+        if v as usize >= geometry.vertices.len() || v as usize >= geometry.uvs.len() {
+            return Err(format!("Triangle uses missing vertex {}", v).into());
         }
-        let mtl_f_path = output.join(&mtl_f_name);
-        if mtl_f_path.try_exists().unwrap() {
-            eprintln!("Output file {:?} already exists, exiting...", mtl_f_path);
-            return;
+        Ok(Corner {
+            position: v as u32,
+            uv: Some(v as u32),
+            normal: None,
+        })
+    };
+
+    let mut mesh = Mesh {
+        positions: geometry.vertices.clone(),
+        uvs: geometry.uvs.iter().map(|x| (x.0, 1.0 - x.1)).collect(),
+        ..Default::default()
+    };
+    for (i, mat) in materials.iter().enumerate() {
+        for p in &mat.polys {
+            let x = match geometry.tris.get(*p as usize) {
+                Some(v) => v,
+                None => return Err(format!("Material uses missing triangle {}", p).into()),
+            };
+            mesh.faces.push(Face {
+                corners: [corner(x.2)?, corner(x.1)?, corner(x.0)?],
+                material: i,
+            });
         }
+        mesh.materials.push(mesh::Material {
+            texture: mat.tex_name.clone(),
+            hints: material_hints(mat),
+        });
+    }
+    Ok(mesh)
+}
 
-        // write object
-        println!("{}Writing object (.obj) to {:?}...", pfx, obj_f_path);
-        let mut obj_f = fs::File::create(obj_f_path).unwrap();
-        obj::write_obj_alt(&mut obj_f, &mtl_f_name, &vtx_buf, &uv_buf, &thing_buf).unwrap();
-
-        // then write material
-        println!("{}Writing material (.mtl) to {:?}...", pfx, mtl_f_path);
-        let mut mtl_f = fs::File::create(mtl_f_path).unwrap();
-        obj::write_mtl(&mut mtl_f, &tex_name).unwrap();
-
-        // check if we can auto copy texture
-        let tex_base = Path::new(&tex_name).file_name().unwrap().to_str().unwrap();
-        let tex_path = match root {
-            Some(ref v) => Path::new(v).join(&tex_name),
-            None => PathBuf::from(&tex_name),
-        };
-        if tex_path.is_file() {
-            // check if we already did
-            let dest = output.join(&tex_base);
-            if dest.try_exists().unwrap() && dest.is_file() {
-                println!("{}Skipping copying texture, already exists", pfx);
-            } else {
-                println!("{}Copying texture to {:?}...", pfx, dest);
-                fs::copy(tex_path, dest).unwrap();
-            }
-        } else {
-            // otherwise texture is manual because we don't know where it is
-            println!(
-                "{}Couldn't find texture, copy texture from \"{}\" to view with textures",
-                pfx, tex_name
-            );
+pub struct ObjHandler;
+
+impl AssetHandler for ObjHandler {
+    fn type_id(&self) -> u8 {
+        1
+    }
+    fn name(&self) -> &'static str {
+        "obj"
+    }
+    fn extensions(&self) -> &'static [&'static str] {
+        &["obj"]
+    }
+    // same magic as map, so map has to be told apart by name
+    fn detect(&self, data: &[u8]) -> bool {
+        data.starts_with(&MAGIC.to_le_bytes())
+    }
+    fn parse(&self, data: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        let x = read(&mut Cursor::new(data))?;
+        to_mesh(&x.geometry, &x.materials)?;
+        Ok(())
+    }
+    fn limits(&self, data: &[u8]) -> Vec<String> {
+        match read(&mut Cursor::new(data)) {
+            Ok(x) => limit_problems(&x.geometry, &x.materials),
+            Err(_) => vec![],
         }
     }
+    fn to_mesh(&self, data: &[u8]) -> Option<Result<Mesh, Box<dyn error::Error>>> {
+        Some(read(&mut Cursor::new(data)).and_then(|x| to_mesh(&x.geometry, &x.materials)))
+    }
+    fn export_targets(&self) -> &'static [&'static str] {
+        &["obj", "gltf"]
+    }
+    fn write(&self, data: &[u8]) -> Option<Result<Vec<u8>, Box<dyn error::Error>>> {
+        let res = read(&mut Cursor::new(data)).and_then(|x| {
+            let mut out = Cursor::new(data.to_vec());
+            write(&mut out, &x)?;
+            Ok(out.into_inner())
+        });
+        Some(res)
+    }
 }
@@ -0,0 +1,507 @@
+use std::{
+    error,
+    fmt::Display,
+    fs,
+    io::{self, Cursor},
+};
+
+use serde::Serialize;
+
+use crate::{
+    archive, common,
+    formats::{animate, carproperty, map, obj, r#box},
+    registry,
+};
+
+// Annotated dump of every field in a file, offsets are worked out from the layout
+// the typed parsers use so a field missing here is a field missing there.
+
+#[derive(Serialize)]
+pub struct Field {
+    pub offset: u64,
+    pub size: u64,
+    pub name: String,
+    // hex of the bytes as stored
+    pub raw: String,
+    pub value: String,
+    pub meaning: String,
+}
+
+struct Dump<'a> {
+    data: &'a [u8],
+    fields: Vec<Field>,
+}
+
+impl Dump<'_> {
+    fn add<V: Display>(&mut self, offset: u64, size: u64, name: &str, value: V, meaning: &str) {
+        let raw = self
+            .data
+            .get(offset as usize..(offset + size) as usize)
+            .map(|x| x.iter().map(|b| format!("{:02x}", b)).collect())
+            .unwrap_or_default();
+        self.fields.push(Field {
+            offset,
+            size,
+            name: name.to_owned(),
+            raw,
+            value: value.to_string(),
+            meaning: meaning.to_owned(),
+        });
+    }
+
+    fn add_string(&mut self, offset: u64, name: &str, text: &str, tail: &[u8], meaning: &str) {
+        let value = if tail.is_empty() {
+            format!("{:?}", text)
+        } else {
+            format!("{:?} (+{} bytes after the NULL)", text, tail.len())
+        };
+        self.add(offset, common::NAME_LEN as u64, name, value, meaning);
+    }
+}
+
+fn vec3(v: (f32, f32, f32)) -> String {
+    format!("({}, {}, {})", v.0, v.1, v.2)
+}
+
+fn vec2(v: (f32, f32)) -> String {
+    format!("({}, {})", v.0, v.1)
+}
+
+fn dump_box(d: &mut Dump, f: &r#box::BoxFile) {
+    let h = &f.header;
+    d.add(0, 4, "magic", r#box::MAGIC, "box magic");
+    d.add(4, 4, "version", common::FORMAT_VERSION, "format version");
+    d.add(
+        8,
+        4,
+        "lump_num",
+        h.lump_num,
+        "number of lumps (parts with their own texture)",
+    );
+    d.add(
+        12,
+        4,
+        "lump_off",
+        h.lump_off,
+        "offset of the lumps, unused by the game",
+    );
+    d.add(
+        16,
+        4,
+        "frame_num",
+        h.frame_num,
+        "number of animation frames",
+    );
+    d.add(20, 4, "box_off", h.box_off, "unused");
+
+    // lump records are read one after another, but each one is followed by its mesh
+    let mut off = r#box::HEADER_LEN;
+    for (i, l) in f.lumps.iter().enumerate() {
+        let n = format!("lumps[{}]", i);
+        d.add(
+            off,
+            4,
+            &format!("{}.pos", n),
+            l.pos,
+            "offset of the mesh data",
+        );
+        d.add(
+            off + 4,
+            4,
+            &format!("{}.vtx_num", n),
+            l.vtx_num,
+            "vertices per frame",
+        );
+        d.add(
+            off + 8,
+            4,
+            &format!("{}.tri_num", n),
+            l.tri_num,
+            "number of triangles",
+        );
+        d.add(
+            off + 12,
+            4,
+            &format!("{}.uvs_num", n),
+            l.uvs_num,
+            "number of uvs",
+        );
+        d.add_string(
+            off + 16,
+            &format!("{}.tex_name", n),
+            &l.tex_name,
+            &l.tex_tail,
+            "texture, relative to the archive root",
+        );
+
+        let mut off_m = l.pos as u64;
+        for (j, uv) in l.uvs.iter().enumerate() {
+            let name = format!("{}.uvs[{}]", n, j);
+            d.add(
+                off_m,
+                8,
+                &name,
+                vec2(*uv),
+                "texture coordinate, v is flipped on export",
+            );
+            off_m += 8;
+        }
+        for (j, t) in l.tris.iter().enumerate() {
+            let name = format!("{}.tris[{}]", n, j);
+            let value = format!("({}/{}, {}/{}, {}/{})", t.a, t.ta, t.b, t.tb, t.c, t.tc);
+            d.add(off_m, 24, &name, value, "vertex/uv index per corner");
+            off_m += 24;
+        }
+        for (j, frame) in l.frames.iter().enumerate() {
+            for (k, v) in frame.iter().enumerate() {
+                let name = format!("{}.frames[{}][{}]", n, j, k);
+                d.add(off_m, 12, &name, vec3(*v), "vertex position");
+                off_m += 12;
+            }
+        }
+        off = off_m;
+    }
+}
+
+// obj and map share the geometry and materials
+fn dump_geometry(d: &mut Dump, geom_off: i32, g: &obj::Geometry) {
+    let mut off = geom_off as u64;
+    for (i, v) in g.vertices.iter().enumerate() {
+        let name = format!("vertices[{}]", i);
+        d.add(off, 12, &name, vec3(*v), "vertex position");
+        off += 12;
+    }
+    for (i, t) in g.tris.iter().enumerate() {
+        let name = format!("tris[{}]", i);
+        let value = format!("({}, {}, {})", t.0, t.1, t.2);
+        d.add(off, 6, &name, value, "vertex (and uv) index per corner");
+        off += 6;
+    }
+    for (i, uv) in g.uvs.iter().enumerate() {
+        let name = format!("uvs[{}]", i);
+        d.add(
+            off,
+            8,
+            &name,
+            vec2(*uv),
+            "texture coordinate, v is flipped on export",
+        );
+        off += 8;
+    }
+}
+
+fn dump_materials(d: &mut Dump, mat_off: i32, materials: &[obj::Material]) {
+    for (i, m) in materials.iter().enumerate() {
+        let off = mat_off as u64 + i as u64 * obj::MATERIAL_LEN;
+        let n = format!("materials[{}]", i);
+        d.add(
+            off,
+            4,
+            &format!("{}.poly_off", n),
+            m.poly_off,
+            "offset of the triangle list",
+        );
+        d.add(
+            off + 4,
+            4,
+            &format!("{}.poly_num", n),
+            m.poly_num,
+            "number of triangles",
+        );
+        d.add(
+            off + 8,
+            4,
+            &format!("{}.unknown", n),
+            m.unknown,
+            "unknown, maybe render flags",
+        );
+        d.add_string(
+            off + 12,
+            &format!("{}.tex_name", n),
+            &m.tex_name,
+            &m.tex_tail,
+            "texture, relative to the archive root",
+        );
+        let off_u = off + 12 + common::NAME_LEN as u64;
+        d.add(
+            off_u,
+            2,
+            &format!("{}.unknown_a", n),
+            m.unknown_a,
+            "unknown, maybe render flags",
+        );
+        d.add(
+            off_u + 2,
+            2,
+            &format!("{}.unknown_b", n),
+            m.unknown_b,
+            "unknown, maybe render flags",
+        );
+
+        for (j, p) in m.polys.iter().enumerate() {
+            let name = format!("{}.polys[{}]", n, j);
+            d.add(
+                m.poly_off as u64 + j as u64 * 2,
+                2,
+                &name,
+                p,
+                "triangle index",
+            );
+        }
+    }
+}
+
+fn dump_obj(d: &mut Dump, f: &obj::ObjFile) {
+    let h = &f.header;
+    d.add(0, 4, "magic", obj::MAGIC, "obj/map magic");
+    d.add(4, 4, "version", common::FORMAT_VERSION, "format version");
+    d.add(8, 4, "geom_off", h.geom_off, "offset of the geometry");
+    d.add(12, 4, "vtx_num", h.vtx_num, "number of vertices");
+    d.add(16, 4, "uv_num", h.uv_num, "number of uvs");
+    d.add(20, 4, "tri_num", h.tri_num, "number of triangles");
+    d.add(24, 12, "max", vec3(h.max), "bounding box max");
+    d.add(36, 12, "min", vec3(h.min), "bounding box min");
+    d.add(48, 4, "mat_num", h.mat_num, "number of materials");
+    d.add(52, 4, "mat_off", h.mat_off, "offset of the materials");
+    dump_geometry(d, h.geom_off, &f.geometry);
+    dump_materials(d, h.mat_off, &f.materials);
+}
+
+fn dump_map(d: &mut Dump, f: &map::MapFile) {
+    let h = &f.header;
+    d.add(0, 4, "magic", obj::MAGIC, "obj/map magic");
+    d.add(4, 4, "version", common::FORMAT_VERSION, "format version");
+    d.add(8, 4, "geom_off", h.geom_off, "offset of the geometry");
+    d.add(12, 4, "split", h.split, "unused");
+    d.add(16, 4, "vtx_num", h.vtx_num, "number of vertices");
+    d.add(20, 4, "uv_num", h.uv_num, "number of uvs");
+    d.add(24, 4, "tri_num", h.tri_num, "number of triangles");
+    d.add(28, 12, "max", vec3(h.max), "bounding box max");
+    d.add(40, 12, "min", vec3(h.min), "bounding box min");
+    d.add(52, 4, "mat_num", h.mat_num, "number of materials");
+    d.add(56, 4, "mat_off", h.mat_off, "offset of the materials");
+    d.add(
+        60,
+        4,
+        "height_off",
+        h.height_off,
+        "offset of the height data, not parsed",
+    );
+    d.add(
+        64,
+        4,
+        "obj_pos_num",
+        h.obj_pos_num,
+        "number of placed objects",
+    );
+    d.add(
+        68,
+        4,
+        "obj_pos_off",
+        h.obj_pos_off,
+        "offset of the placed objects",
+    );
+    d.add(
+        72,
+        4,
+        "cell_num",
+        h.cell_num,
+        "number of visibility cells, not parsed",
+    );
+    d.add(
+        76,
+        4,
+        "cell_off",
+        h.cell_off,
+        "offset of the visibility cells",
+    );
+    d.add(80, 12, "grid_max", vec3(h.grid_max), "visibility grid max");
+    d.add(92, 12, "grid_min", vec3(h.grid_min), "visibility grid min");
+    d.add(
+        104,
+        4,
+        "uv_anim_num",
+        h.uv_anim_num,
+        "number of uv animations, not parsed",
+    );
+    d.add(
+        108,
+        4,
+        "tex_anim_num",
+        h.tex_anim_num,
+        "number of texture animations, not parsed",
+    );
+    d.add(
+        112,
+        4,
+        "uv_anim_off",
+        h.uv_anim_off,
+        "offset of the uv animations",
+    );
+    d.add(
+        116,
+        4,
+        "tex_anim_off",
+        h.tex_anim_off,
+        "offset of the texture animations",
+    );
+    dump_geometry(d, h.geom_off, &f.geometry);
+    dump_materials(d, h.mat_off, &f.materials);
+
+    for (i, p) in f.obj_pos.iter().enumerate() {
+        let off = h.obj_pos_off as u64 + i as u64 * map::OBJ_POS_LEN;
+        let n = format!("obj_pos[{}]", i);
+        d.add(
+            off,
+            12,
+            &format!("{}.vec", n),
+            vec3(p.vec),
+            "object position",
+        );
+        d.add(
+            off + 12,
+            4,
+            &format!("{}.angle", n),
+            p.angle,
+            "rotation, negated by the game",
+        );
+        d.add(off + 16, 4, &format!("{}.type", n), p.type_, "object type");
+    }
+}
+
+// offset of the first record in animate.dat/carproperty.dat
+fn table_start(d: &mut Dump, version: Option<i32>, count: usize) -> u64 {
+    let mut off = 0;
+    if let Some(v) = version {
+        d.add(0, 4, "version", v, "format version");
+        off += 4;
+    }
+    d.add(off, 4, "count", count, "number of records");
+    off + 4
+}
+
+fn dump_extra(d: &mut Dump, off: u64, name: &str, extra: &[u32]) {
+    for (j, w) in extra.iter().enumerate() {
+        let value = format!("{} / {}", w, f32::from_bits(*w));
+        let name = format!("{}.extra[{}]", name, j);
+        d.add(off + j as u64 * 4, 4, &name, value, "unknown, as u32 / f32");
+    }
+}
+
+fn dump_animate(d: &mut Dump, f: &animate::Animate) {
+    let mut off = table_start(d, f.version, f.records.len());
+    for (i, r) in f.records.iter().enumerate() {
+        let n = format!("records[{}]", i);
+        d.add_string(
+            off,
+            &format!("{}.model", n),
+            &r.model,
+            &r.model_tail,
+            "maybe the model it plays on (unconfirmed)",
+        );
+        let off_f = off + common::NAME_LEN as u64;
+        d.add(
+            off_f,
+            4,
+            &format!("{}.first_frame", n),
+            r.first_frame,
+            "unknown, maybe first frame (unconfirmed)",
+        );
+        d.add(
+            off_f + 4,
+            4,
+            &format!("{}.last_frame", n),
+            r.last_frame,
+            "unknown, maybe last frame (unconfirmed)",
+        );
+        dump_extra(d, off + animate::RECORD_MIN, &n, &r.extra);
+        off += animate::RECORD_MIN + r.extra.len() as u64 * 4;
+    }
+    if !f.trailing.is_empty() {
+        d.add(
+            off,
+            f.trailing.len() as u64,
+            "trailing",
+            f.trailing.len(),
+            "bytes after the last record",
+        );
+    }
+}
+
+fn dump_carproperty(d: &mut Dump, f: &carproperty::CarProperty) {
+    let mut off = table_start(d, f.version, f.cars.len());
+    for (i, c) in f.cars.iter().enumerate() {
+        let n = format!("cars[{}]", i);
+        d.add_string(
+            off,
+            &format!("{}.model", n),
+            &c.model,
+            &c.model_tail,
+            "car body model",
+        );
+        let mut off_f = off + common::NAME_LEN as u64;
+        for (name, guess, value) in c.fields() {
+            let meaning = format!("unknown, maybe {} (unconfirmed)", guess);
+            d.add(off_f, 4, &format!("{}.{}", n, name), value, &meaning);
+            off_f += 4;
+        }
+        dump_extra(d, off + carproperty::RECORD_MIN, &n, &c.extra);
+        off += carproperty::RECORD_MIN + c.extra.len() as u64 * 4;
+    }
+    if !f.trailing.is_empty() {
+        d.add(
+            off,
+            f.trailing.len() as u64,
+            "trailing",
+            f.trailing.len(),
+            "bytes after the last car",
+        );
+    }
+}
+
+// picks the parser the same way pack picks the type id
+pub fn inspect_data(name: &str, data: &[u8]) -> Result<Vec<Field>, Box<dyn error::Error>> {
+    let mut d = Dump {
+        data,
+        fields: vec![],
+    };
+    let mut c = Cursor::new(data);
+    let handler = registry::by_name(name).or_else(|| registry::detect(data));
+    match handler.map(|x| x.type_id()) {
+        Some(0) => dump_box(&mut d, &r#box::read(&mut c)?),
+        Some(1) => dump_obj(&mut d, &obj::read(&mut c)?),
+        Some(2) => dump_map(&mut d, &map::read(&mut c)?),
+        Some(5) => dump_animate(&mut d, &animate::read(&mut c)?),
+        Some(6) => dump_carproperty(&mut d, &carproperty::read(&mut c)?),
+        _ => return Err(format!("Don't know how to inspect {:?}", name).into()),
+    }
+    Ok(d.fields)
+}
+
+pub fn inspect(input: &str, json: bool) -> Result<(), Box<dyn error::Error>> {
+    let (name, data) = match archive::split_entry_path(input) {
+        Some((archive_path, name)) => (name.to_owned(), archive::read_entry(archive_path, name)?.1),
+        None => (input.to_owned(), fs::read(input)?),
+    };
+    let fields = inspect_data(&name, &data)?;
+
+    if json {
+        serde_json::to_writer_pretty(io::stdout(), &fields)?;
+        println!();
+        return Ok(());
+    }
+
+    for x in fields {
+        // 100 byte strings would push everything off screen
+        let raw = if x.raw.len() > 32 {
+            format!("{}...", &x.raw[..32])
+        } else {
+            x.raw
+        };
+        println!(
+            "0x{:06x} {:<32} {:<35} {:<24} {}",
+            x.offset, x.name, raw, x.value, x.meaning
+        );
+    }
+    Ok(())
+}
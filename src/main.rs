@@ -1,22 +1,42 @@
+mod archive;
 mod common;
 mod conversion;
+mod dat;
+mod delta;
+mod diff;
+mod export;
 mod formats;
+mod inspect;
+mod mesh;
+mod modpack;
+mod output;
+mod probe;
+mod registry;
+mod seek;
+mod verify;
+mod zipdat;
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use clap::{Parser, Subcommand};
-use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use clap::{Args, Parser, Subcommand};
 use std::{
-    fs,
-    io::{Cursor, Read, Write},
+    error, fs,
+    io::{self, Read},
     path::{Path, PathBuf},
 };
 use walkdir::WalkDir;
 
-use crate::formats::*;
+use crate::{
+    conversion::Options,
+    formats::*,
+    output::Policy,
+};
 
 #[derive(Parser)]
 #[command(version, about)]
 struct Cli {
+    /// Legacy encoding for names that aren't UTF-8, e.g. euc-kr
+    #[arg(long, global = true)]
+    encoding: Option<String>,
+
     #[command(subcommand)]
     command: CliCommands,
 }
@@ -29,6 +49,10 @@ enum CliCommands {
         #[arg(short, long)]
         output: Option<String>,
 
+        /// Deflate level from 0 (none) to 9 (smallest), doesn't change the content hash
+        #[arg(short = 'l', long, default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+        compression_level: u32,
+
         /// Folder to pack, e.g. common
         folder: String,
     },
@@ -37,9 +61,118 @@ enum CliCommands {
         #[arg(short, long)]
         output: Option<String>,
 
+        #[command(flatten)]
+        policy: PolicyArgs,
+
         /// File to unpack, e.g. common.dat
         file: String,
     },
+    List {
+        /// Parse every entry and check that the known ones write back the same
+        #[arg(short, long)]
+        validate: bool,
+
+        /// File to list, e.g. common.dat, lists the supported types if left out
+        file: Option<String>,
+    },
+    Verify {
+        /// Write the entries that are still complete to a new archive
+        #[arg(short, long)]
+        salvage: bool,
+
+        /// Output file for --salvage, default is <file> without ".dat" plus "_salvaged.dat"
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Archive to check, e.g. common.dat
+        file: String,
+    },
+    Diff {
+        /// Print JSON instead of text
+        #[arg(short, long)]
+        json: bool,
+
+        /// Archive to compare against, e.g. the original common.dat
+        a: String,
+
+        /// Changed archive, e.g. from a mod
+        b: String,
+    },
+    MakeMod {
+        /// Output folder, default is <folder> plus "_mod"
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Archive the folder was unpacked from, e.g. common.dat
+        vanilla: String,
+
+        /// Unpacked folder with the changes, e.g. common
+        folder: String,
+    },
+    ApplyMod {
+        /// Output file, default is <file> without ".dat" plus "_modded.dat"
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// What to do when two mods change the same entry differently
+        #[arg(long, value_enum, default_value_t = modpack::OnConflict::Fail)]
+        on_conflict: modpack::OnConflict,
+
+        /// Archive the mods were made from, e.g. common.dat
+        file: String,
+
+        /// Mod packages to apply, in order
+        #[arg(required = true)]
+        mods: Vec<String>,
+    },
+    Dat {
+        #[command(subcommand)]
+        command: DatCommands,
+    },
+    Delta {
+        #[command(subcommand)]
+        command: DeltaCommands,
+    },
+    Index {
+        /// Archive to index, the index is written next to it as <file>.idx
+        file: String,
+    },
+    Dat2zip {
+        /// Output file, default is <file> without ".dat" plus ".zip"
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Archive to convert, e.g. common.dat
+        file: String,
+    },
+    Zip2dat {
+        /// Output file, default is <file> without ".zip" plus ".dat"
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Zip to convert, made by dat2zip or any other zip
+        file: String,
+    },
+    ExportAll {
+        /// Output folder, default is <file> without ".dat" plus "_export"
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Extracted root directory or archive to take textures from if the archive
+        /// doesn't have them, can be given more than once
+        #[arg(short, long)]
+        root: Vec<String>,
+
+        /// Copy textures into the folders their names have instead of next to the model
+        #[arg(long)]
+        mirror_textures: bool,
+
+        #[command(flatten)]
+        policy: PolicyArgs,
+
+        /// File to export, e.g. common.dat
+        file: String,
+    },
 
     // Commands for converting extracted files
     Convert {
@@ -47,46 +180,269 @@ enum CliCommands {
         #[arg(short, long)]
         output: Option<String>,
 
-        /// Extracted root directory, set to copy assets automatically if it's somewhere else
+        /// Extracted root directory or archive to take textures from, can be given more
+        /// than once and they are searched in order
         #[arg(short, long)]
-        root: Option<String>,
+        root: Vec<String>,
+
+        /// Format to convert to, obj or gltf, default is the first one the type supports
+        #[arg(short, long)]
+        format: Option<String>,
+
+        /// Copy textures into the folders their names have instead of next to the model
+        #[arg(long)]
+        mirror_textures: bool,
+
+        #[command(flatten)]
+        policy: PolicyArgs,
 
-        /// File to conert, e.g. man.box
+        /// Files, archive entries, folders or globs to convert, e.g. man.box,
+        /// common.dat:models/man.box, common/models or "common/**/*.box"
+        #[arg(required = true)]
+        file: Vec<String>,
+    },
+
+    // Commands for figuring out unknown formats
+    Probe {
+        /// Probe every entry with this type id, file has to be an archive then
+        #[arg(short, long)]
+        r#type: Option<u8>,
+
+        /// File or archive entry to probe, e.g. thing.mdl or common.dat:thing.mdl
+        file: String,
+    },
+
+    Inspect {
+        /// Print JSON instead of a table
+        #[arg(short, long)]
+        json: bool,
+
+        /// File or archive entry to inspect, e.g. man.box or common.dat:man.box
+        file: String,
+    },
+
+    // Commands for editing single files
+    Animate {
+        #[command(subcommand)]
+        command: AnimateCommands,
+    },
+    Carprop {
+        #[command(subcommand)]
+        command: CarpropCommands,
+    },
+    Material {
+        /// Output file, default is <name>.edited.obj (or .map) next to the input
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Change the input file itself instead of writing a new one
+        #[arg(long, conflicts_with = "output")]
+        in_place: bool,
+
+        #[command(flatten)]
+        policy: PolicyArgs,
+
+        /// Material to change, see the list printed without any changes
+        #[arg(short, long)]
+        index: Option<usize>,
+
+        /// New value for the unknown i32 after poly_num
+        #[arg(long)]
+        unknown: Option<i32>,
+
+        /// New value for the first unknown u16 after the texture name
+        #[arg(long)]
+        unknown_a: Option<u16>,
+
+        /// New value for the second unknown u16 after the texture name
+        #[arg(long)]
+        unknown_b: Option<u16>,
+
+        /// File to list/change materials of, e.g. house.obj
         file: String,
     },
 }
 
-// MDL files are not mapped yet since the name/extension is unknown
-fn rsrc_type_mapper(peth: &Path) -> Option<u8> {
-    // first try to match whole name
-    match peth.file_name().unwrap().to_str().unwrap() {
-        // these dont use special extensions and they only appear once,
-        // so i guess this is the way to do it?
-        "path.dat" => Some(4),
-        "animate.dat" => Some(5),
-        "carproperty.dat" => Some(6),
-        // then try to match extension
-        _ => match peth
-            .extension()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_lowercase()
-            .as_str()
-        {
-            "box" => Some(0),
-            "obj" => Some(1),
-            "map" => Some(2),
-            "hmp" => Some(3),
-            // there might be other texture formats but these are known
-            "png" | "jpg" => Some(10),
-            // unsupported input file
-            _ => None,
-        },
+// what to do when outputs are already there, default is to stop
+#[derive(Args)]
+#[group(multiple = false)]
+struct PolicyArgs {
+    /// Overwrite outputs that already exist
+    #[arg(long)]
+    force: bool,
+
+    /// Only write outputs that don't exist yet
+    #[arg(long)]
+    skip_existing: bool,
+
+    /// Only write outputs that don't exist yet or changed
+    #[arg(long)]
+    update: bool,
+}
+
+impl PolicyArgs {
+    fn policy(&self) -> Policy {
+        if self.force {
+            Policy::Force
+        } else if self.skip_existing {
+            Policy::SkipExisting
+        } else if self.update {
+            Policy::Update
+        } else {
+            Policy::Abort
+        }
     }
 }
 
-fn pack(folder: &String, output: Option<String>) {
+#[derive(Subcommand)]
+enum DatCommands {
+    /// Add a file as a new entry, textures go in front of the models
+    Add {
+        /// Output file, default is to change the archive in place
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Entry name, default is the path of the file as given
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// Type id, default is to go by the name like pack does
+        #[arg(short, long)]
+        r#type: Option<u8>,
+
+        /// Archive to add to, e.g. common.dat
+        file: String,
+
+        /// File to add, e.g. textures/new.png
+        input: String,
+    },
+    /// Remove entries
+    Rm {
+        /// Output file, default is to change the archive in place
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Archive to remove from, e.g. common.dat
+        file: String,
+
+        /// Entries to remove, e.g. models/man.box
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+    /// Replace the data of an entry, the type id stays
+    Replace {
+        /// Output file, default is to change the archive in place
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Archive to change, e.g. common.dat
+        file: String,
+
+        /// Entry to replace, e.g. models/man.box
+        name: String,
+
+        /// File with the new data, e.g. man.box
+        input: String,
+    },
+    /// Rename an entry, the type id stays
+    Mv {
+        /// Output file, default is to change the archive in place
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Archive to change, e.g. common.dat
+        file: String,
+
+        /// Entry to rename, e.g. models/man.box
+        name: String,
+
+        /// New name, e.g. models/woman.box
+        new_name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DeltaCommands {
+    /// Make a patch that turns one archive into another
+    Create {
+        /// Output file, default is <target> without ".dat" plus ".delta"
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Archive the patch applies to, e.g. the original common.dat
+        source: String,
+
+        /// Archive the patch turns it into
+        target: String,
+    },
+    /// Turn an archive into what the patch was made from
+    Apply {
+        /// Output file, default is <source> without ".dat" plus "_patched.dat"
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Archive the patch was made for, e.g. common.dat
+        source: String,
+
+        /// Patch made with delta create
+        patch: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum AnimateCommands {
+    /// List the records and look up the models they seem to point to
+    Show {
+        /// Extracted root directory, default is the folder the file is in
+        #[arg(short, long)]
+        root: Option<String>,
+
+        /// File to show, e.g. animate.dat
+        file: String,
+    },
+    /// Write the records out as JSON
+    Export {
+        /// Output file, default is <file>.json
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// File to export, e.g. animate.dat
+        file: String,
+    },
+    /// Build the binary file back from JSON
+    Import {
+        /// Output file, default is <file> without ".json"
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// File to import, e.g. animate.dat.json
+        file: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum CarpropCommands {
+    /// Write the car properties out as TOML or JSON
+    Export {
+        /// Output file, default is <file>.toml, use a .json extension for JSON
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// File to export, e.g. carproperty.dat
+        file: String,
+    },
+    /// Check the values and build the binary file back from TOML or JSON
+    Import {
+        /// Output file, default is <file> without ".toml"/".json"
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// File to import, e.g. carproperty.dat.toml
+        file: String,
+    },
+}
+
+fn pack(folder: &String, output: Option<String>, level: u32) {
     // check if we can use input directory
     let folder_path = Path::new(folder);
     if !folder_path.is_dir() {
@@ -122,11 +478,13 @@ fn pack(folder: &String, output: Option<String>) {
 
         let peth = x.path();
         // skip files without any compatible resourcetype
-        let mapped = rsrc_type_mapper(peth);
-        if mapped.is_none() {
-            eprintln!("Skipping unsupported file {:?}", peth);
-            continue;
-        }
+        let handler = match registry::by_name(peth.to_str().unwrap()) {
+            Some(v) => v,
+            None => {
+                eprintln!("Skipping unsupported file {:?}", peth);
+                continue;
+            }
+        };
 
         // push file info
         files.push((
@@ -136,12 +494,12 @@ fn pack(folder: &String, output: Option<String>) {
                 .unwrap()
                 .replace("\\", "/"),
             peth.to_owned(),
-            mapped.unwrap(),
+            handler.type_id(),
         ));
     }
 
     // exit if no files
-    if files.len() < 1 {
+    if files.is_empty() {
         eprintln!("No files found to pack, exiting...");
         return;
     }
@@ -152,41 +510,81 @@ fn pack(folder: &String, output: Option<String>) {
     // so this is a bandaid fix to that problem since i dont have control over which order
     // the walkdir function gets files.
     // thankfully the id works great for this :).
-    files.sort_by(|a, b| b.2.cmp(&a.2));
+    // within a type they go by name, so the same folder always gives the same archive
+    files.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
 
-    // store header in memory buffer
-    let mut head = Cursor::new(vec![]);
-
-    // write all file names and types
-    head.write_i32::<LittleEndian>(files.len() as i32).unwrap();
+    // the header has fixed size fields, so check everything fits before writing
+    let mut problems = 0;
+    if files.len() as u64 > archive::MAX_LEN {
+        eprintln!("{} files don't fit in an archive, exiting...", files.len());
+        return;
+    }
     for x in &files {
-        // filename
-        head.write_u8(x.0.len() as u8).unwrap();
-        head.write_all(x.0.as_bytes()).unwrap();
-        // type
-        head.write_u8(x.2).unwrap();
+        let len = fs::metadata(&x.1).unwrap().len();
+        for e in archive::entry_problems(&x.0, len) {
+            eprintln!("{:?}: {}", x.1, e);
+            problems += 1;
+        }
+    }
+    if problems > 0 {
+        eprintln!(
+            "{} problems with the files, nothing written, exiting...",
+            problems
+        );
+        return;
     }
-    head.flush().unwrap();
 
-    // then compress and write result
-    let f = fs::File::create(out_file).unwrap();
-    let mut comp = DeflateEncoder::new(f, Compression::best());
-    comp.write_all(&head.into_inner()).unwrap();
+    // write all file names and types, then compress and write the files one by one
+    let entries: Vec<archive::Entry> = files
+        .iter()
+        .map(|x| archive::Entry {
+            name: x.0.clone(),
+            type_: x.2,
+        })
+        .collect();
+    let res = output::write_atomic_with(&out_file, |f| {
+        let mut comp = archive::ArchiveWriter::with_level(
+            io::BufWriter::new(f),
+            &entries,
+            flate2::Compression::new(level),
+        )?;
+        let mut in_buf = vec![];
+        for x in &files {
+            // read file into memory
+            let mut in_f = fs::File::open(&x.1)?;
+            in_f.read_to_end(&mut in_buf)?;
+            let handler = registry::by_type(x.2).unwrap();
+            // packing broken files is allowed, but it's good to know
+            if let Err(e) = handler.parse(&in_buf) {
+                eprintln!("Warning: {:?} doesn't look valid: {}", x.1, e);
+            }
+            // files breaking the limits parse fine but break in game, those aren't
+            for e in handler.limits(&in_buf) {
+                eprintln!("{:?}: {}", x.1, e);
+                problems += 1;
+            }
+            comp.add(&in_buf)?;
+            in_buf.clear();
+        }
+        if problems > 0 {
+            return Err(format!("{} problems with the files, nothing written", problems).into());
+        }
+        comp.finish()?.into_inner().map_err(|e| e.into_error())?;
+        Ok(())
+    });
+    if let Err(e) = res {
+        eprintln!("{}, exiting...", e);
+        return;
+    }
 
-    let mut in_buf = vec![];
-    for x in &files {
-        // read file into memory
-        let mut in_f = fs::File::open(&x.1).unwrap();
-        let readed = in_f.read_to_end(&mut in_buf).unwrap();
-        // write length and data
-        comp.write_i32::<LittleEndian>(readed as i32).unwrap();
-        comp.write_all(&in_buf[0..readed]).unwrap();
-        in_buf.clear();
+    // read back from the file, so it's also what ended up on disk
+    match archive::content_hash(&out_file) {
+        Ok(v) => println!("{} entries, content hash: {}", files.len(), v),
+        Err(e) => eprintln!("Couldn't read {:?} back: {}, exiting...", out_file, e),
     }
-    comp.flush().unwrap();
 }
 
-fn unpack(file: &String, output: Option<String>) {
+fn unpack(file: &String, output: Option<String>, policy: Policy) {
     // check if we can use input file
     let file_path = Path::new(file);
     if !file_path.is_file() {
@@ -194,9 +592,6 @@ fn unpack(file: &String, output: Option<String>) {
         return;
     }
 
-    let f = fs::File::open(file).unwrap();
-    let mut rdr = DeflateDecoder::new(f);
-
     // figure out output directory
     let out_dir = match output {
         Some(v) => PathBuf::from(v),
@@ -205,52 +600,146 @@ fn unpack(file: &String, output: Option<String>) {
             PathBuf::from(file_stem)
         }
     };
-    // check if we can use it
-    if out_dir.try_exists().unwrap() {
+    // check if we can use it, the other policies go file by file
+    if policy == Policy::Abort && out_dir.try_exists().unwrap() {
         eprintln!("{:?} already exists, exiting...", out_dir);
         return;
     }
 
-    // get all objects
-    let mut objects = vec![];
-    let objects_n = rdr.read_i32::<LittleEndian>().unwrap();
-    for _ in 0..objects_n {
-        let len = rdr.read_u8().unwrap();
-        let filename = common::read_string(&mut rdr, len).unwrap();
-        let type_ = rdr.read_u8().unwrap(); // type
-        objects.push((filename, type_));
-    }
+    // get all objects, names are checked before anything is written since they can
+    // come from anywhere
+    let mut rdr = match archive::ArchiveReader::open(file_path) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}, exiting...", e);
+            return;
+        }
+    };
+    let checks = archive::check_names(&rdr.entries);
 
     // output em
-    for o in objects {
-        let len = rdr.read_i32::<LittleEndian>().unwrap();
-        println!("\"{}\", length: {}, type: {}", o.0, len, o.1);
-
-        let mut buffer = vec![0u8; len as usize];
-        rdr.read_exact(&mut buffer).unwrap();
+    let mut written = 0;
+    let mut skipped = 0;
+    let mut rejected = 0;
+    loop {
+        let (i, buffer) = match rdr.next_entry() {
+            Ok(Some(v)) => v,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("{}, exiting...", e);
+                return;
+            }
+        };
+        let o = &rdr.entries[i];
+        println!(
+            "\"{}\", length: {}, type: {} ({})",
+            o.name,
+            buffer.len(),
+            o.type_,
+            registry::type_name(o.type_)
+        );
 
-        let out_path = out_dir.join(&o.0);
-        fs::create_dir_all(out_path.parent().unwrap()).unwrap();
+        for x in &checks[i].warnings {
+            eprintln!("Warning: {}", x);
+        }
+        let out_path = match &checks[i].path {
+            Ok(v) => out_dir.join(v),
+            Err(e) => {
+                eprintln!("Not unpacking \"{}\", it {}", o.name, e);
+                rejected += 1;
+                continue;
+            }
+        };
+        if !output::should_write(policy, &out_path, &buffer).unwrap() {
+            skipped += 1;
+            continue;
+        }
+        output::write_atomic(&out_path, &buffer).unwrap();
+        written += 1;
+    }
+    if policy != Policy::Abort || rejected > 0 {
+        println!(
+            "{} written, {} skipped, {} rejected",
+            written, skipped, rejected
+        );
+    }
+}
 
-        let mut out = fs::File::create(&out_path).unwrap();
-        out.write_all(&buffer).unwrap();
+fn list_types() {
+    for x in registry::handlers() {
+        let patterns: Vec<String> = x
+            .file_names()
+            .iter()
+            .map(|v| v.to_string())
+            .chain(x.extensions().iter().map(|v| format!("*.{}", v)))
+            .collect();
+        println!(
+            "{:>2} {:<12} {:<24} {}",
+            x.type_id(),
+            x.name(),
+            patterns.join(" "),
+            x.export_targets().join(" ")
+        );
     }
 }
 
-fn convert(input: String, root: Option<String>, output: Option<String>) {
-    // check if we can use input file
-    let input_path = Path::new(&input);
-    if !input_path.is_file() {
-        eprintln!("Input file {:?} does not exist, exiting...", input_path);
-        return;
+fn list(file: &str, validate: bool) -> Result<(), Box<dyn error::Error>> {
+    let mut rdr = archive::ArchiveReader::open(Path::new(file))?;
+    let checks = archive::check_names(&rdr.entries);
+    let mut failed = 0;
+    while let Some((i, data)) = rdr.next_entry()? {
+        let o = &rdr.entries[i];
+        let handler = registry::by_type(o.type_);
+        println!(
+            "\"{}\", length: {}, type: {} ({})",
+            o.name,
+            data.len(),
+            o.type_,
+            registry::type_name(o.type_)
+        );
+        if !validate {
+            continue;
+        }
+
+        let mut problems = checks[i].warnings.clone();
+        if let Err(e) = &checks[i].path {
+            problems.push(format!("can't be unpacked, it {}", e));
+        }
+        // the game goes by the type id, but pack goes by the name
+        if let Some(v) = registry::by_name(&o.name) {
+            if v.type_id() != o.type_ {
+                problems.push(format!("name says type {}", v.name()));
+            }
+        }
+        if let Some(h) = handler {
+            if let Err(e) = h.parse(&data) {
+                problems.push(e.to_string());
+            } else {
+                match h.write(&data) {
+                    Some(Err(e)) => problems.push(format!("can't write back: {}", e)),
+                    Some(Ok(v)) if v != data => problems.push(String::from("writes back different")),
+                    _ => (),
+                }
+            }
+        }
+        for x in &problems {
+            println!("  {}", x);
+        }
+        if !problems.is_empty() {
+            failed += 1;
+        }
+    }
+    if validate {
+        println!("{} entries, {} with problems", rdr.entries.len(), failed);
     }
+    Ok(())
+}
 
-    // check if specified root exists
-    if root.is_some() {
-        let h = root.as_ref().unwrap();
-        let root_path = Path::new(&h);
-        if !root_path.is_dir() {
-            eprintln!("Root directory {:?} does not exist, exiting...", root_path);
+fn convert(files: Vec<String>, root: Vec<String>, output: Option<String>, mut opts: Options) {
+    // check if specified roots exist, textures are searched in them in order
+    for x in &root {
+        if let Err(e) = opts.textures.add_root(Path::new(x)) {
+            eprintln!("{}, exiting...", e);
             return;
         }
     }
@@ -272,30 +761,287 @@ fn convert(input: String, root: Option<String>, output: Option<String>) {
         return;
     }
 
-    // open file
-    let input_file = fs::File::open(input_path).unwrap();
+    // without roots textures are relative to the current dir like before,
+    // folders being converted are most likely a root too
+    let entries = files.iter().filter(|x| archive::split_entry_path(x).is_some());
+    if root.is_empty() && entries.count() < files.len() {
+        opts.textures.add_dir(Path::new("."));
+    }
+    for x in &files {
+        if Path::new(x).is_dir() {
+            opts.textures.add_dir(Path::new(x));
+        }
+    }
+
+    // find everything to convert
+    let mut jobs = vec![];
+    for x in &files {
+        match conversion::collect_jobs(x, out_dir) {
+            Ok(v) => jobs.extend(v),
+            Err(e) => {
+                eprintln!("{}, exiting...", e);
+                return;
+            }
+        }
+    }
 
     // magik
-    match input_path.extension().unwrap().to_str().unwrap() {
-        "box" => r#box::box_to_obj(input_file, input_path, out_dir, root),
-        "obj" => obj::obj_to_obj(input_file, input_path, out_dir, root),
-        "map" => map::map_to_obj(input_file, input_path, out_dir, root),
-        _ => ()
+    let single = files.len() == 1
+        && (archive::split_entry_path(&files[0]).is_some() || Path::new(&files[0]).is_file());
+    if single {
+        if let Err(e) = jobs[0].run(&opts) {
+            eprintln!("{}, exiting...", e);
+        }
+    } else {
+        conversion::convert_jobs(&jobs, &opts);
     }
 }
 
 fn main() {
     let cli = Cli::parse();
+    if let Some(v) = &cli.encoding {
+        if let Err(e) = common::set_legacy_encoding(v) {
+            eprintln!("{}, exiting...", e);
+            return;
+        }
+    }
 
     match cli.command {
-        CliCommands::Pack { output, folder } => {
-            pack(&folder, output);
+        CliCommands::Pack {
+            output,
+            compression_level,
+            folder,
+        } => {
+            pack(&folder, output, compression_level);
+        }
+        CliCommands::Unpack {
+            output,
+            policy,
+            file,
+        } => {
+            unpack(&file, output, policy.policy());
+        }
+        CliCommands::List { validate, file } => match file {
+            Some(v) => {
+                if let Err(e) = list(&v, validate) {
+                    eprintln!("{}, exiting...", e);
+                }
+            }
+            None => list_types(),
+        },
+        CliCommands::ExportAll {
+            output,
+            root,
+            mirror_textures,
+            policy,
+            file,
+        } => {
+            let opts = Options {
+                mirror_textures,
+                policy: policy.policy(),
+                ..Default::default()
+            };
+            if let Err(e) = export::export_all(Path::new(&file), output, &root, opts) {
+                eprintln!("{}, exiting...", e);
+            }
         }
-        CliCommands::Unpack { output, file } => {
-            unpack(&file, output);
+        CliCommands::Convert {
+            output,
+            root,
+            format,
+            mirror_textures,
+            policy,
+            file,
+        } => {
+            let opts = Options {
+                mirror_textures,
+                policy: policy.policy(),
+                format,
+                ..Default::default()
+            };
+            convert(file, root, output, opts);
         }
-        CliCommands::Convert { output, root, file } => {
-            convert(file, root, output);
+        CliCommands::Probe { r#type, file } => {
+            if let Err(e) = probe::probe(&file, r#type) {
+                eprintln!("{}, exiting...", e);
+            }
         }
+        CliCommands::MakeMod {
+            output,
+            vanilla,
+            folder,
+        } => {
+            if let Err(e) = modpack::make_mod(Path::new(&vanilla), Path::new(&folder), output) {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::ApplyMod {
+            output,
+            on_conflict,
+            file,
+            mods,
+        } => {
+            if let Err(e) = modpack::apply_mods(Path::new(&file), &mods, output, on_conflict) {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::Dat { command } => {
+            let (file, output, edits) = match command {
+                DatCommands::Add {
+                    output,
+                    name,
+                    r#type,
+                    file,
+                    input,
+                } => {
+                    let name = name.unwrap_or_else(|| input.replace('\\', "/"));
+                    let guess = registry::by_name(&name).map(|x| x.type_id());
+                    let type_ = match r#type.or(guess) {
+                        Some(v) => v,
+                        None => {
+                            eprintln!(
+                                "Don't know the type of \"{}\", give it with --type, exiting...",
+                                name
+                            );
+                            return;
+                        }
+                    };
+                    (
+                        file,
+                        output,
+                        vec![dat::Edit::Add(name, type_, PathBuf::from(input))],
+                    )
+                }
+                DatCommands::Rm {
+                    output,
+                    file,
+                    names,
+                } => (
+                    file,
+                    output,
+                    names.into_iter().map(dat::Edit::Remove).collect(),
+                ),
+                DatCommands::Replace {
+                    output,
+                    file,
+                    name,
+                    input,
+                } => (
+                    file,
+                    output,
+                    vec![dat::Edit::Replace(name, PathBuf::from(input))],
+                ),
+                DatCommands::Mv {
+                    output,
+                    file,
+                    name,
+                    new_name,
+                } => (file, output, vec![dat::Edit::Rename(name, new_name)]),
+            };
+            if let Err(e) = dat::edit(Path::new(&file), output, edits) {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::Delta { command } => {
+            let res = match command {
+                DeltaCommands::Create {
+                    output,
+                    source,
+                    target,
+                } => delta::create(Path::new(&source), Path::new(&target), output),
+                DeltaCommands::Apply {
+                    output,
+                    source,
+                    patch,
+                } => delta::apply(Path::new(&source), Path::new(&patch), output),
+            };
+            if let Err(e) = res {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::Dat2zip { output, file } => {
+            if let Err(e) = zipdat::dat2zip(Path::new(&file), output) {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::Zip2dat { output, file } => {
+            if let Err(e) = zipdat::zip2dat(Path::new(&file), output) {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::Index { file } => {
+            if let Err(e) = seek::create(Path::new(&file)) {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::Verify {
+            salvage,
+            output,
+            file,
+        } => {
+            if let Err(e) = verify::verify(Path::new(&file), salvage, output) {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::Diff { json, a, b } => {
+            if let Err(e) = diff::diff(Path::new(&a), Path::new(&b), json) {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::Inspect { json, file } => {
+            if let Err(e) = inspect::inspect(&file, json) {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::Animate { command } => match command {
+            AnimateCommands::Show { root, file } => {
+                if let Err(e) = animate::show(Path::new(&file), root) {
+                    eprintln!("{}, exiting...", e);
+                }
+            }
+            AnimateCommands::Export { output, file } => {
+                if let Err(e) = animate::export(Path::new(&file), output) {
+                    eprintln!("{}, exiting...", e);
+                }
+            }
+            AnimateCommands::Import { output, file } => {
+                if let Err(e) = animate::import(Path::new(&file), output) {
+                    eprintln!("{}, exiting...", e);
+                }
+            }
+        },
+        CliCommands::Material {
+            output,
+            in_place,
+            policy,
+            index,
+            unknown,
+            unknown_a,
+            unknown_b,
+            file,
+        } => {
+            let edit = obj::MaterialEdit {
+                index,
+                unknown,
+                unknown_a,
+                unknown_b,
+            };
+            let res = obj::edit_material(Path::new(&file), output, in_place, policy.policy(), edit);
+            if let Err(e) = res {
+                eprintln!("{}, exiting...", e);
+            }
+        }
+        CliCommands::Carprop { command } => match command {
+            CarpropCommands::Export { output, file } => {
+                if let Err(e) = carproperty::export(Path::new(&file), output) {
+                    eprintln!("{}, exiting...", e);
+                }
+            }
+            CarpropCommands::Import { output, file } => {
+                if let Err(e) = carproperty::import(Path::new(&file), output) {
+                    eprintln!("{}, exiting...", e);
+                }
+            }
+        },
     }
 }
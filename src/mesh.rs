@@ -0,0 +1,115 @@
+// Common mesh representation every format is read into and every exporter writes
+// from, so a new input or output format only needs one adapter.
+//
+// Conventions, readers do the conversion:
+// - faces are counter clockwise (the game files are the other way around)
+// - uvs have v going up, like OBJ and glTF expect
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Corner {
+    pub position: u32,
+    pub uv: Option<u32>,
+    pub normal: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Face {
+    pub corners: [Corner; 3],
+    // index into Mesh::materials
+    pub material: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Material {
+    // as stored in the file, relative to the archive root
+    pub texture: String,
+    // things we don't understand yet, written as comments where the output allows it
+    pub hints: Vec<String>,
+}
+
+// a point of interest without geometry, like the object positions in a map
+#[derive(Debug, Clone, Default)]
+pub struct Marker {
+    pub name: String,
+    pub position: (f32, f32, f32),
+    pub hints: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub positions: Vec<(f32, f32, f32)>,
+    pub uvs: Vec<(f32, f32)>,
+    pub normals: Option<Vec<(f32, f32, f32)>>,
+    pub faces: Vec<Face>,
+    pub materials: Vec<Material>,
+    // animation frames after the first one (which is positions), same length as positions
+    pub frames: Vec<Vec<(f32, f32, f32)>>,
+    // only written by outputs that have a scene, OBJ drops them
+    pub markers: Vec<Marker>,
+}
+
+impl Mesh {
+    // only the faces of one material, with unused vertices dropped and indices
+    // remapped. keeps the original order so output stays stable
+    pub fn submesh(&self, material: usize) -> Mesh {
+        let faces: Vec<&Face> = self
+            .faces
+            .iter()
+            .filter(|x| x.material == material)
+            .collect();
+
+        // old index -> new index, u32::MAX for unused
+        fn remap(used: impl Iterator<Item = u32>, len: usize) -> Vec<u32> {
+            let mut map = vec![u32::MAX; len];
+            for x in used {
+                map[x as usize] = 0;
+            }
+            let mut next = 0;
+            for x in map.iter_mut() {
+                if *x == 0 {
+                    *x = next;
+                    next += 1;
+                }
+            }
+            map
+        }
+        fn pick<T: Copy>(src: &[T], map: &[u32]) -> Vec<T> {
+            src.iter()
+                .zip(map)
+                .filter(|x| *x.1 != u32::MAX)
+                .map(|x| *x.0)
+                .collect()
+        }
+
+        let corners = || faces.iter().flat_map(|x| x.corners.iter());
+        let pos_map = remap(corners().map(|x| x.position), self.positions.len());
+        let uv_map = remap(corners().filter_map(|x| x.uv), self.uvs.len());
+        let normal_map = self
+            .normals
+            .as_ref()
+            .map(|n| remap(corners().filter_map(|x| x.normal), n.len()));
+
+        Mesh {
+            positions: pick(&self.positions, &pos_map),
+            uvs: pick(&self.uvs, &uv_map),
+            normals: self
+                .normals
+                .as_ref()
+                .map(|n| pick(n, normal_map.as_ref().unwrap())),
+            faces: faces
+                .iter()
+                .map(|x| Face {
+                    corners: x.corners.map(|c| Corner {
+                        position: pos_map[c.position as usize],
+                        uv: c.uv.map(|v| uv_map[v as usize]),
+                        normal: c.normal.map(|v| normal_map.as_ref().unwrap()[v as usize]),
+                    }),
+                    material: 0,
+                })
+                .collect(),
+            materials: vec![self.materials[material].clone()],
+            frames: self.frames.iter().map(|x| pick(x, &pos_map)).collect(),
+            markers: vec![],
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error, fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{archive, common, output, registry};
+
+// A mod package is a folder with only what a mod changes compared to the archive
+// it was made from:
+//
+//   mod.toml   the manifest below
+//   files/     payloads of added and changed entries, by entry name
+//
+// so mods can be passed around without the original assets in them.
+
+pub const MANIFEST: &str = "mod.toml";
+const FILES: &str = "files";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Add,
+    Change,
+    Remove,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModEntry {
+    pub name: String,
+    pub action: Action,
+    #[serde(rename = "type")]
+    pub type_: u8,
+    // payload in the package relative to the manifest, not there for removed ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    // sha256 of the new payload
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    // sha256 of the payload in the base archive, for changed and removed ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_hash: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    // archive::content_hash of the archive the mod was made from
+    pub base: String,
+    // only to tell people which archive that was
+    pub base_name: String,
+    pub entries: Vec<ModEntry>,
+}
+
+// every file under the folder by its path with / separators
+fn folder_files(folder: &Path) -> BTreeMap<String, PathBuf> {
+    WalkDir::new(folder)
+        .into_iter()
+        .filter_map(|x| x.ok())
+        .filter(|x| x.file_type().is_file())
+        .filter_map(|x| {
+            let rel = x
+                .path()
+                .strip_prefix(folder)
+                .ok()?
+                .to_str()?
+                .replace('\\', "/");
+            Some((rel, x.into_path()))
+        })
+        .collect()
+}
+
+// compares an unpacked and edited folder with the archive it came from
+pub fn make_mod(
+    vanilla: &Path,
+    folder: &Path,
+    output: Option<String>,
+) -> Result<(), Box<dyn error::Error>> {
+    if !folder.is_dir() {
+        return Err(format!("Input directory {:?} does not exist", folder).into());
+    }
+    let out_dir = match output {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(format!(
+            "{}_mod",
+            folder.file_name().unwrap().to_str().unwrap()
+        )),
+    };
+    if out_dir.try_exists()? {
+        return Err(format!("{:?} already exists", out_dir).into());
+    }
+
+    let base = archive::content_hash(vanilla)?;
+    let entries = archive::read_all(vanilla)?;
+    let names: Vec<archive::Entry> = entries.iter().map(|x| x.0.clone()).collect();
+    let checks = archive::check_names(&names);
+    let mut files = folder_files(folder);
+
+    let mut mod_entries = vec![];
+    // payloads to write, by their path in the package
+    let mut payloads: Vec<(String, Vec<u8>)> = vec![];
+    for ((entry, data), check) in entries.iter().zip(&checks) {
+        // can't be in a folder, so it can't have been edited either
+        let rel = match &check.path {
+            Ok(v) => v.to_str().unwrap().replace('\\', "/"),
+            Err(e) => {
+                eprintln!("Leaving \"{}\" alone, it {}", entry.name, e);
+                continue;
+            }
+        };
+        let base_hash = Some(common::hash_hex(data));
+        let path = match files.remove(&rel) {
+            Some(v) => v,
+            None => {
+                mod_entries.push(ModEntry {
+                    name: entry.name.clone(),
+                    action: Action::Remove,
+                    type_: entry.type_,
+                    file: None,
+                    hash: None,
+                    base_hash,
+                });
+                continue;
+            }
+        };
+        let new = fs::read(path)?;
+        if new == *data {
+            continue;
+        }
+        // the archive's type id stays, the game goes by it
+        let file = format!("{}/{}", FILES, rel);
+        mod_entries.push(ModEntry {
+            name: entry.name.clone(),
+            action: Action::Change,
+            type_: entry.type_,
+            file: Some(file.clone()),
+            hash: Some(common::hash_hex(&new)),
+            base_hash,
+        });
+        payloads.push((file, new));
+    }
+
+    // whatever is left is new, with the type going by the name like pack does
+    for (rel, path) in files {
+        let handler = match registry::by_name(&rel) {
+            Some(v) => v,
+            None => {
+                eprintln!("Skipping unsupported file {:?}", path);
+                continue;
+            }
+        };
+        let new = fs::read(path)?;
+        let file = format!("{}/{}", FILES, rel);
+        mod_entries.push(ModEntry {
+            name: rel,
+            action: Action::Add,
+            type_: handler.type_id(),
+            file: Some(file.clone()),
+            hash: Some(common::hash_hex(&new)),
+            base_hash: None,
+        });
+        payloads.push((file, new));
+    }
+
+    if mod_entries.is_empty() {
+        return Err(format!("{:?} has no changes compared to {:?}", folder, vanilla).into());
+    }
+    let count = |a: Action| mod_entries.iter().filter(|x| x.action == a).count();
+    println!(
+        "{} changed, {} added, {} removed",
+        count(Action::Change),
+        count(Action::Add),
+        count(Action::Remove)
+    );
+
+    let manifest = Manifest {
+        base,
+        base_name: vanilla.file_name().unwrap().to_str().unwrap().to_owned(),
+        entries: mod_entries,
+    };
+    println!("Writing mod to {:?}...", out_dir);
+    for (file, data) in &payloads {
+        output::write_atomic(&out_dir.join(file), data)?;
+    }
+    output::write_atomic(
+        &out_dir.join(MANIFEST),
+        toml::to_string_pretty(&manifest)?.as_bytes(),
+    )?;
+    Ok(())
+}
+
+// what to do when two mods change the same entry differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Don't write anything
+    Fail,
+    /// The mod given first wins
+    First,
+    /// The mod given last wins
+    Last,
+}
+
+// payloads of a package by entry name
+type Payloads = HashMap<String, Vec<u8>>;
+
+// reads a package, the payloads are checked against their hashes
+fn read_mod(dir: &Path) -> Result<(Manifest, Payloads), Box<dyn error::Error>> {
+    let path = dir.join(MANIFEST);
+    let text = fs::read_to_string(&path).map_err(|e| format!("Couldn't read {:?}: {}", path, e))?;
+    let manifest: Manifest =
+        toml::from_str(&text).map_err(|e| format!("Couldn't parse {:?}: {}", path, e))?;
+    let mut payloads = HashMap::new();
+    for x in &manifest.entries {
+        // removes carry nothing, everything else needs its payload
+        let file = match (x.action, &x.file, &x.hash) {
+            (Action::Remove, None, _) => continue,
+            (Action::Remove, Some(_), _) => {
+                return Err(format!("Remove of \"{}\" in {:?} has a file", x.name, path).into())
+            }
+            (_, Some(v), Some(_)) => v,
+            _ => {
+                return Err(
+                    format!("\"{}\" in {:?} is missing its file or hash", x.name, path).into(),
+                )
+            }
+        };
+        let data = fs::read(dir.join(archive::safe_path(file)?))?;
+        if x.hash.as_deref() != Some(common::hash_hex(&data).as_str()) {
+            return Err(format!("{:?} in {:?} doesn't match its hash", file, dir).into());
+        }
+        payloads.insert(x.name.clone(), data);
+    }
+    Ok((manifest, payloads))
+}
+
+// same outcome, so two mods doing it isn't a conflict
+fn same_change(a: &ModEntry, b: &ModEntry) -> bool {
+    a.action == b.action && a.hash == b.hash && a.type_ == b.type_
+}
+
+// puts the mods on top of the archive they were made from, in the order given
+pub fn apply_mods(
+    vanilla: &Path,
+    mods: &[String],
+    output: Option<String>,
+    on_conflict: OnConflict,
+) -> Result<(), Box<dyn error::Error>> {
+    let out_file = match output {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(format!(
+            "{}_modded.dat",
+            vanilla.file_stem().unwrap().to_str().unwrap()
+        )),
+    };
+    if out_file.try_exists()? {
+        return Err(format!("Output file {:?} already exists", out_file).into());
+    }
+
+    // mods made for another archive would remove or replace the wrong things
+    let base = archive::content_hash(vanilla)?;
+    let mut loaded = vec![];
+    for x in mods {
+        let (manifest, payloads) = read_mod(Path::new(x))?;
+        if manifest.base != base {
+            return Err(format!(
+                "{:?} was made for a different {} than {:?}",
+                x, manifest.base_name, vanilla
+            )
+            .into());
+        }
+        loaded.push((x, manifest, payloads));
+    }
+
+    // which mod gets to do what to each entry
+    let mut chosen: HashMap<&str, (usize, &ModEntry)> = HashMap::new();
+    let mut conflicts = 0;
+    for (i, (name, manifest, _)) in loaded.iter().enumerate() {
+        for x in &manifest.entries {
+            let (j, other) = match chosen.get(x.name.as_str()) {
+                Some(v) => *v,
+                None => {
+                    chosen.insert(&x.name, (i, x));
+                    continue;
+                }
+            };
+            if same_change(other, x) {
+                continue;
+            }
+            conflicts += 1;
+            eprintln!(
+                "Conflict: {:?} and {:?} both {} \"{}\"",
+                loaded[j].0,
+                name,
+                match (other.action, x.action) {
+                    (Action::Remove, Action::Remove) => "remove",
+                    (Action::Add, Action::Add) => "add",
+                    _ => "change",
+                },
+                x.name
+            );
+            if on_conflict == OnConflict::Last {
+                chosen.insert(&x.name, (i, x));
+            }
+        }
+    }
+    if conflicts > 0 && on_conflict == OnConflict::Fail {
+        return Err(format!(
+            "{} conflicts, pick which mod wins with --on-conflict",
+            conflicts
+        )
+        .into());
+    }
+
+    // untouched entries stay where they are with their type
+    let mut entries = vec![];
+    let (mut changed, mut added, mut removed) = (0, 0, 0);
+    for (entry, data) in archive::read_all(vanilla)? {
+        match chosen.remove(entry.name.as_str()) {
+            Some((_, x)) if x.action == Action::Remove => removed += 1,
+            Some((i, x)) => {
+                changed += 1;
+                let data = loaded[i].2[&x.name].clone();
+                entries.push((entry, data));
+            }
+            None => entries.push((entry, data)),
+        }
+    }
+
+    // whatever is left wasn't in the archive, only adds can make new entries
+    if let Some((i, x)) = chosen.values().find(|x| x.1.action == Action::Change) {
+        return Err(format!(
+            "{:?} changes \"{}\" which isn't in {:?}",
+            loaded[*i].0, x.name, vanilla
+        )
+        .into());
+    }
+
+    // new entries go where pack would have put them
+    let mut new: Vec<(usize, &ModEntry)> = chosen
+        .into_values()
+        .filter(|x| x.1.action == Action::Add)
+        .collect();
+    new.sort_by_key(|x| (x.0, &x.1.name));
+    for (i, x) in new {
+        let pos = archive::insert_position(entries.iter().map(|v| &v.0), x.type_);
+        let entry = archive::Entry {
+            name: x.name.clone(),
+            type_: x.type_,
+        };
+        entries.insert(pos, (entry, loaded[i].2[&x.name].clone()));
+        added += 1;
+    }
+
+    println!("{} changed, {} added, {} removed", changed, added, removed);
+    println!("Writing {} entries to {:?}...", entries.len(), out_file);
+    output::write_atomic(&out_file, &archive::write_all(&entries)?)?;
+    Ok(())
+}
@@ -0,0 +1,271 @@
+use std::{
+    collections::HashMap,
+    error, fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::common;
+
+// What to do about outputs that are already there, the same for every command
+// that writes files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    // don't touch anything if something is in the way
+    #[default]
+    Abort,
+    // overwrite everything
+    Force,
+    // only write what isn't there yet
+    SkipExisting,
+    // only write what is missing or changed, compared by hash
+    Update,
+}
+
+// returned when nothing was written because of the policy, so batches can tell it
+// apart from a real failure
+#[derive(Debug)]
+pub struct Skipped {
+    pub reason: String,
+    // outputs that are there from before
+    pub existing: Vec<PathBuf>,
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
+impl error::Error for Skipped {}
+
+// the process id alone isn't enough, parallel conversions write the same textures
+static TMP_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// shared files this run already wrote or is writing, with the hash of their data
+static SHARED: Mutex<Option<HashMap<PathBuf, String>>> = Mutex::new(None);
+
+fn tmp_path(dest: &Path) -> PathBuf {
+    let name = dest.file_name().unwrap().to_string_lossy();
+    let n = TMP_COUNT.fetch_add(1, Ordering::Relaxed);
+    dest.with_file_name(format!(".{}.{}.{}.tmp", name, std::process::id(), n))
+}
+
+// true if nothing else in this run took the shared file yet, it's ours to write then
+fn claim_shared(path: &Path, data: &[u8]) -> bool {
+    let hash = common::hash_hex(data);
+    let mut shared = SHARED.lock().unwrap();
+    let shared = shared.get_or_insert_with(HashMap::new);
+    match shared.get(path) {
+        Some(v) => {
+            if *v != hash {
+                eprintln!(
+                    "Warning: {:?} was already written with different contents, keeping it",
+                    path
+                );
+            }
+            false
+        }
+        None => {
+            shared.insert(path.to_owned(), hash);
+            true
+        }
+    }
+}
+
+fn release_shared(path: &Path) {
+    if let Some(v) = SHARED.lock().unwrap().as_mut() {
+        v.remove(path);
+    }
+}
+
+// writes next to dest and renames over it, so there's never half a file at dest
+pub fn write_atomic(dest: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(v) = dest.parent() {
+        fs::create_dir_all(v)?;
+    }
+    let tmp = tmp_path(dest);
+    if let Err(e) = fs::write(&tmp, data).and_then(|_| fs::rename(&tmp, dest)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// same but for writing that goes straight to the file instead of through memory
+pub fn write_atomic_with<F>(dest: &Path, write: F) -> Result<(), Box<dyn error::Error>>
+where
+    F: FnOnce(fs::File) -> Result<(), Box<dyn error::Error>>,
+{
+    if let Some(v) = dest.parent() {
+        fs::create_dir_all(v)?;
+    }
+    let tmp = tmp_path(dest);
+    let res = fs::File::create(&tmp)
+        .map_err(|e| e.into())
+        .and_then(write)
+        .and_then(|_| Ok(fs::rename(&tmp, dest)?));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+// whether a single file should be written, Abort has to be checked by the caller
+// since it's about the whole output and not one file
+pub fn should_write(policy: Policy, dest: &Path, data: &[u8]) -> io::Result<bool> {
+    if !dest.try_exists()? {
+        return Ok(true);
+    }
+    Ok(match policy {
+        Policy::Abort | Policy::Force => true,
+        Policy::SkipExisting => false,
+        Policy::Update => common::hash_hex(&fs::read(dest)?) != common::hash_hex(data),
+    })
+}
+
+struct OutputFile {
+    path: PathBuf,
+    data: Vec<u8>,
+    // used by several outputs (textures), being there already is fine
+    shared: bool,
+}
+
+// Everything one conversion writes, collected first so nothing is written when it
+// would be skipped anyway and the files show up together.
+#[derive(Default)]
+pub struct OutputSet {
+    files: Vec<OutputFile>,
+}
+
+impl OutputSet {
+    pub fn add(&mut self, path: PathBuf, data: Vec<u8>) {
+        self.files.push(OutputFile {
+            path,
+            data,
+            shared: false,
+        });
+    }
+
+    pub fn add_shared(&mut self, path: PathBuf, data: Vec<u8>) {
+        self.files.push(OutputFile {
+            path,
+            data,
+            shared: true,
+        });
+    }
+
+    fn skipped(&self, reason: String) -> Box<dyn error::Error> {
+        let existing = self
+            .files
+            .iter()
+            .filter(|x| x.path.exists())
+            .map(|x| x.path.clone())
+            .collect();
+        Skipped { reason, existing }.into()
+    }
+
+    // returns the paths of every output, also those that were already there
+    pub fn commit(self, policy: Policy) -> Result<Vec<PathBuf>, Box<dyn error::Error>> {
+        if policy == Policy::Abort {
+            for x in self.files.iter().filter(|x| !x.shared) {
+                if x.path.try_exists()? {
+                    return Err(self.skipped(format!("Output file {:?} already exists", x.path)));
+                }
+            }
+        }
+
+        let mut write = vec![];
+        let mut skipped = 0;
+        for x in &self.files {
+            let changed = should_write(policy, &x.path, &x.data)?;
+            // shared files only get replaced when asked to
+            let keep = x.shared
+                && matches!(policy, Policy::Abort | Policy::SkipExisting)
+                && x.path.try_exists()?;
+            if keep {
+                if fs::read(&x.path)? != x.data {
+                    eprintln!(
+                        "Warning: {:?} already exists with different contents, keeping it",
+                        x.path
+                    );
+                }
+            } else if changed {
+                write.push(x);
+            } else if !x.shared {
+                println!(
+                    "Skipping {:?}, {}",
+                    x.path,
+                    match policy {
+                        Policy::Update => "unchanged",
+                        _ => "already exists",
+                    }
+                );
+                skipped += 1;
+            }
+        }
+        if write.iter().all(|x| x.shared) && skipped > 0 {
+            let what = match policy {
+                Policy::Update => "Outputs are up to date",
+                _ => "Outputs already exist",
+            };
+            return Err(self.skipped(what.to_owned()));
+        }
+
+        // several conversions running at once can want the same texture, only one
+        // of them writes it
+        write.retain(|x| !x.shared || claim_shared(&x.path, &x.data));
+
+        // everything goes to temporary files first and is only moved into place once
+        // all of them were written
+        let mut staged = vec![];
+        for x in &write {
+            let res = match x.path.parent() {
+                Some(v) => fs::create_dir_all(v),
+                None => Ok(()),
+            };
+            let tmp = tmp_path(&x.path);
+            if let Err(e) = res.and_then(|_| fs::write(&tmp, &x.data)) {
+                let _ = fs::remove_file(&tmp);
+                for (v, _) in staged {
+                    let _ = fs::remove_file(v);
+                }
+                // nothing was written, so someone else can have them
+                for x in write.iter().filter(|x| x.shared) {
+                    release_shared(&x.path);
+                }
+                return Err(e.into());
+            }
+            staged.push((tmp, &x.path));
+        }
+        for (tmp, path) in staged {
+            println!("Writing {:?}...", path);
+            fs::rename(tmp, path)?;
+        }
+        Ok(self.files.into_iter().map(|x| x.path).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tmp_names_are_unique() {
+        let dest = Path::new("out/tex.png");
+        assert_ne!(tmp_path(dest), tmp_path(dest));
+    }
+
+    #[test]
+    fn shared_files_are_claimed_once() {
+        let dest = Path::new("claim_test/tex.png");
+        assert!(claim_shared(dest, b"a"));
+        assert!(!claim_shared(dest, b"a"));
+        assert!(!claim_shared(dest, b"b"));
+        release_shared(dest);
+        assert!(claim_shared(dest, b"b"));
+    }
+}
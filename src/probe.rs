@@ -0,0 +1,281 @@
+use std::{error, fs, path::Path};
+
+use crate::{
+    archive, common,
+    formats::{obj, r#box},
+};
+
+// Guesses at the structure of files we don't know yet (MDL for example).
+// Everything here is a heuristic, it only points at places worth looking at.
+
+// don't flood the terminal, the first few hits are usually enough
+const MAX_SHOWN: usize = 20;
+// how much of the start of the file counts as "header" for offset/count pairs
+const HEADER_LEN: usize = 256;
+// runs shorter than this are too likely to be noise
+const MIN_F32_RUN: usize = 9;
+const MIN_U16_RUN: usize = 12;
+
+struct Run {
+    off: usize,
+    len: usize,
+}
+
+fn word(data: &[u8], off: usize) -> i32 {
+    i32::from_le_bytes(data[off..off + 4].try_into().unwrap())
+}
+
+fn float(data: &[u8], off: usize) -> f32 {
+    f32::from_le_bytes(data[off..off + 4].try_into().unwrap())
+}
+
+fn half(data: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(data[off..off + 2].try_into().unwrap())
+}
+
+// small integers and flags turn into denormals or huge numbers as floats,
+// real coordinates/uvs are somewhere in between
+fn plausible_f32(v: f32) -> bool {
+    v == 0.0 || (v.is_finite() && (1e-5..=1e5).contains(&v.abs()))
+}
+
+fn find_f32_runs(data: &[u8]) -> Vec<Run> {
+    let mut runs = vec![];
+    // u16 arrays can leave floats only 2 byte aligned, so try both
+    for phase in [0, 2] {
+        let mut off = phase;
+        while off + 4 <= data.len() {
+            let start = off;
+            let mut nonzero = 0;
+            while off + 4 <= data.len() && plausible_f32(float(data, off)) {
+                if float(data, off) != 0.0 {
+                    nonzero += 1;
+                }
+                off += 4;
+            }
+            let len = (off - start) / 4;
+            if len >= MIN_F32_RUN && nonzero >= 3 {
+                runs.push(Run { off: start, len });
+            }
+            off += 4;
+        }
+    }
+    runs.sort_by_key(|x| x.off);
+    runs
+}
+
+fn find_u16_runs(data: &[u8], bound: usize) -> Vec<Run> {
+    let mut runs = vec![];
+    let mut off = 0;
+    while off + 2 <= data.len() {
+        let start = off;
+        let mut nonzero = 0;
+        while off + 2 <= data.len() && (half(data, off) as usize) < bound {
+            if half(data, off) != 0 {
+                nonzero += 1;
+            }
+            off += 2;
+        }
+        let len = (off - start) / 2;
+        if len >= MIN_U16_RUN && nonzero * 4 >= len {
+            runs.push(Run { off: start, len });
+        }
+        off += 2;
+    }
+    runs
+}
+
+// NULL padded strings like the 100 byte texture names
+fn find_strings(data: &[u8]) -> Vec<(usize, String)> {
+    let mut found = vec![];
+    let mut off = 0;
+    while off + common::NAME_LEN <= data.len() {
+        let field = &data[off..off + common::NAME_LEN];
+        let text_len = field
+            .iter()
+            .position(|x| !(0x20..0x7f).contains(x))
+            .unwrap_or(common::NAME_LEN);
+        if (3..common::NAME_LEN).contains(&text_len) && field[text_len..].iter().all(|x| *x == 0) {
+            found.push((
+                off,
+                String::from_utf8_lossy(&field[..text_len]).into_owned(),
+            ));
+            off += common::NAME_LEN;
+        } else {
+            off += 1;
+        }
+    }
+    found
+}
+
+fn print_limited(lines: Vec<String>) {
+    if lines.is_empty() {
+        println!("  none");
+    }
+    for x in lines.iter().take(MAX_SHOWN) {
+        println!("  {}", x);
+    }
+    if lines.len() > MAX_SHOWN {
+        println!("  ... and {} more", lines.len() - MAX_SHOWN);
+    }
+}
+
+fn describe_target(off: usize, f32_runs: &[Run], u16_runs: &[Run]) -> &'static str {
+    if f32_runs.iter().any(|x| x.off == off) {
+        ", start of a f32 run"
+    } else if u16_runs.iter().any(|x| x.off == off) {
+        ", start of a u16 run"
+    } else {
+        ""
+    }
+}
+
+pub fn probe_data(data: &[u8]) {
+    // known magic first, no need to guess if we already know it
+    if data.len() >= 4 {
+        match word(data, 0) {
+            r#box::MAGIC => println!("Known magic: box"),
+            obj::MAGIC => println!("Known magic: obj/map"),
+            v => println!(
+                "Unknown magic: 0x{:08x} {:?}",
+                v,
+                String::from_utf8_lossy(&data[..4])
+            ),
+        }
+    }
+
+    println!("Version marker (FORMAT_VERSION):");
+    let mut lines = vec![];
+    let mut off = 0;
+    while off + 4 <= data.len() {
+        if word(data, off) == common::FORMAT_VERSION {
+            lines.push(format!("0x{:06x}", off));
+        }
+        off += 4;
+    }
+    print_limited(lines);
+
+    let f32_runs = find_f32_runs(data);
+    // indices can't go past the biggest vertex array we found
+    let bound = f32_runs.iter().map(|x| x.len / 3).max().unwrap_or(0);
+    let u16_runs = if bound > 1 {
+        find_u16_runs(data, bound)
+    } else {
+        vec![]
+    };
+
+    println!("Offset/count pairs in the first {} bytes:", HEADER_LEN);
+    let mut lines = vec![];
+    let mut off = 0;
+    while off + 4 <= data.len().min(HEADER_LEN) {
+        let v = word(data, off);
+        if v >= 8 && (v as usize) < data.len() && v % 2 == 0 {
+            let left = data.len() - v as usize;
+            // the count is usually right next to it
+            for c_off in [off.checked_sub(4), Some(off + 4)] {
+                let c_off = match c_off {
+                    Some(v) if v + 4 <= data.len().min(HEADER_LEN) => v,
+                    _ => continue,
+                };
+                let c = word(data, c_off);
+                if c > 0 && (c as usize) * 2 <= left {
+                    lines.push(format!(
+                        "0x{:06x}: offset 0x{:06x}, count {} at 0x{:06x} (up to {} bytes each{})",
+                        off,
+                        v,
+                        c,
+                        c_off,
+                        left / c as usize,
+                        describe_target(v as usize, &f32_runs, &u16_runs)
+                    ));
+                }
+            }
+        }
+        off += 4;
+    }
+    print_limited(lines);
+
+    println!("Runs of valid f32 triples:");
+    let lines = f32_runs
+        .iter()
+        .map(|x| {
+            format!(
+                "0x{:06x}: {} floats ({} triples, {} pairs){}",
+                x.off,
+                x.len,
+                x.len / 3,
+                x.len / 2,
+                if x.len % 3 == 0 {
+                    ""
+                } else {
+                    ", not a multiple of 3"
+                }
+            )
+        })
+        .collect();
+    print_limited(lines);
+
+    println!("Runs of u16 indices below {}:", bound);
+    let lines = u16_runs
+        .iter()
+        .map(|x| {
+            let max = (0..x.len).map(|i| half(data, x.off + i * 2)).max().unwrap();
+            format!(
+                "0x{:06x}: {} values ({} triangles), max {}",
+                x.off,
+                x.len,
+                x.len / 3,
+                max
+            )
+        })
+        .collect();
+    print_limited(lines);
+
+    println!("NULL padded {} byte strings:", common::NAME_LEN);
+    let lines = find_strings(data)
+        .into_iter()
+        .map(|(off, s)| format!("0x{:06x}: \"{}\"", off, s))
+        .collect();
+    print_limited(lines);
+}
+
+pub fn probe(input: &str, type_: Option<u8>) -> Result<(), Box<dyn error::Error>> {
+    // every entry of a type, handy for the ones we can't name yet
+    if let Some(t) = type_ {
+        let mut rdr = archive::ArchiveReader::open(Path::new(input))?;
+        while let Some((i, data)) = rdr.next_entry()? {
+            let entry = &rdr.entries[i];
+            if entry.type_ == t {
+                println!(
+                    "\"{}\", length: {}, type: {}",
+                    entry.name,
+                    data.len(),
+                    entry.type_
+                );
+                probe_data(&data);
+                println!();
+            }
+        }
+        return Ok(());
+    }
+
+    let data = match archive::split_entry_path(input) {
+        Some((archive_path, name)) => {
+            let (entry, data) = archive::read_entry(archive_path, name)?;
+            println!(
+                "\"{}\", length: {}, type: {}",
+                entry.name,
+                data.len(),
+                entry.type_
+            );
+            data
+        }
+        None => {
+            let data = fs::read(Path::new(input))?;
+            println!("{:?}, length: {}", input, data.len());
+            data
+        }
+    };
+    probe_data(&data);
+    Ok(())
+}
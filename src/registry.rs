@@ -0,0 +1,206 @@
+use std::{
+    error,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    conversion::{self, Options},
+    formats::{animate, carproperty, map, obj, r#box},
+    mesh::Mesh,
+};
+
+// Everything that knows about a resource type lives behind this trait, pack, unpack,
+// list and convert only go through the registry below. Supporting a new type means
+// implementing this and adding it to HANDLERS.
+pub trait AssetHandler: Sync {
+    // type id stored in the archive header
+    fn type_id(&self) -> u8;
+
+    fn name(&self) -> &'static str;
+
+    // whole file names that always mean this type, lowercase
+    fn file_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    // extensions that mean this type, lowercase
+    fn extensions(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    // for when there's no name to go by
+    fn detect(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    // full parse to check the data, fine by default for types we can't parse
+    fn parse(&self, _data: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        Ok(())
+    }
+
+    // limits of the format that a file can still break without failing to parse,
+    // like names filling their whole field. pack refuses files with any
+    fn limits(&self, _data: &[u8]) -> Vec<String> {
+        vec![]
+    }
+
+    // None if it isn't something with geometry
+    fn to_mesh(&self, _data: &[u8]) -> Option<Result<Mesh, Box<dyn error::Error>>> {
+        None
+    }
+
+    // what convert can turn it into, the first one is the default
+    fn export_targets(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn export(
+        &self,
+        data: &[u8],
+        target: &str,
+        input: &Path,
+        output: &Path,
+        opts: &Options,
+    ) -> Result<Vec<PathBuf>, Box<dyn error::Error>> {
+        match (target, self.to_mesh(data)) {
+            ("obj", Some(mesh)) => conversion::obj::export_mesh(&mesh?, input, output, opts),
+            ("gltf", Some(mesh)) => conversion::gltf::export_mesh(&mesh?, input, output, opts),
+            _ => Err(format!("Can't convert {} to {}", self.name(), target).into()),
+        }
+    }
+
+    // other files this one points at, as stored. models point at their textures
+    fn references(&self, data: &[u8]) -> Vec<String> {
+        let mut refs: Vec<String> = match self.to_mesh(data) {
+            Some(Ok(mesh)) => mesh.materials.into_iter().map(|x| x.texture).collect(),
+            _ => vec![],
+        };
+        refs.sort();
+        refs.dedup();
+        refs
+    }
+
+    // named values that say what's in it without going through every byte, diff
+    // compares these. models give their counts and textures
+    fn summary(&self, data: &[u8]) -> Vec<(String, String)> {
+        let mesh = match self.to_mesh(data) {
+            Some(Ok(v)) => v,
+            Some(Err(e)) => return vec![(String::from("error"), e.to_string())],
+            None => return vec![],
+        };
+        let mut s = vec![
+            (String::from("vertices"), mesh.positions.len().to_string()),
+            (String::from("uvs"), mesh.uvs.len().to_string()),
+            (String::from("faces"), mesh.faces.len().to_string()),
+            (String::from("frames"), mesh.frames.len().to_string()),
+            (String::from("markers"), mesh.markers.len().to_string()),
+            (String::from("materials"), mesh.materials.len().to_string()),
+        ];
+        for (i, x) in mesh.materials.iter().enumerate() {
+            s.push((
+                format!("materials[{}].texture", i),
+                format!("{:?}", x.texture),
+            ));
+        }
+        s
+    }
+
+    // parses and writes the data back, None if there is no writer for the type
+    fn write(&self, _data: &[u8]) -> Option<Result<Vec<u8>, Box<dyn error::Error>>> {
+        None
+    }
+}
+
+// types we only know the name of
+struct RawHandler {
+    type_id: u8,
+    name: &'static str,
+    file_names: &'static [&'static str],
+    extensions: &'static [&'static str],
+    magic: &'static [&'static [u8]],
+}
+
+impl AssetHandler for RawHandler {
+    fn type_id(&self) -> u8 {
+        self.type_id
+    }
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn file_names(&self) -> &'static [&'static str] {
+        self.file_names
+    }
+    fn extensions(&self) -> &'static [&'static str] {
+        self.extensions
+    }
+    fn detect(&self, data: &[u8]) -> bool {
+        self.magic.iter().any(|x| data.starts_with(x))
+    }
+}
+
+// MDL files are not mapped yet since the name/extension is unknown
+static HANDLERS: &[&dyn AssetHandler] = &[
+    &r#box::BoxHandler,
+    &obj::ObjHandler,
+    &map::MapHandler,
+    &RawHandler {
+        type_id: 3,
+        name: "hmp",
+        file_names: &[],
+        extensions: &["hmp"],
+        magic: &[],
+    },
+    // these dont use special extensions and they only appear once,
+    // so i guess this is the way to do it?
+    &RawHandler {
+        type_id: 4,
+        name: "path",
+        file_names: &["path.dat"],
+        extensions: &[],
+        magic: &[],
+    },
+    &animate::AnimateHandler,
+    &carproperty::CarPropertyHandler,
+    // there might be other texture formats but these are known
+    &RawHandler {
+        type_id: 10,
+        name: "texture",
+        file_names: &[],
+        extensions: &["png", "jpg"],
+        magic: &[b"\x89PNG", b"\xff\xd8\xff"],
+    },
+];
+
+pub fn handlers() -> &'static [&'static dyn AssetHandler] {
+    HANDLERS
+}
+
+pub fn by_type(type_id: u8) -> Option<&'static dyn AssetHandler> {
+    HANDLERS.iter().find(|x| x.type_id() == type_id).copied()
+}
+
+// first try to match whole name, then the extension
+pub fn by_name(name: &str) -> Option<&'static dyn AssetHandler> {
+    let path = Path::new(name);
+    let file_name = path.file_name()?.to_str()?.to_lowercase();
+    if let Some(v) = HANDLERS
+        .iter()
+        .find(|x| x.file_names().contains(&file_name.as_str()))
+    {
+        return Some(*v);
+    }
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    HANDLERS
+        .iter()
+        .find(|x| x.extensions().contains(&ext.as_str()))
+        .copied()
+}
+
+pub fn detect(data: &[u8]) -> Option<&'static dyn AssetHandler> {
+    HANDLERS.iter().find(|x| x.detect(data)).copied()
+}
+
+// readable name for a type id, also for the ones we don't know
+pub fn type_name(type_id: u8) -> &'static str {
+    by_type(type_id).map(|x| x.name()).unwrap_or("unknown")
+}
@@ -0,0 +1,757 @@
+use std::{
+    collections::HashMap,
+    error, fs,
+    io::{BufReader, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{archive, common, output};
+
+// The archive is one deflate stream, so getting at the last entry means inflating
+// everything in front of it. A deflate stream can be picked up again at the start
+// of any block as long as the 32KB before it are known, so the index remembers
+// those every now and then, together with where every entry is:
+//
+//   8 bytes magic
+//   64 bytes hex sha256 of the archive file, it's only used while that still matches
+//   u64 length of everything inside the stream
+//   u32 entry count
+//   per entry: u8 name length, name, u8 type, u64 offset of the payload, u32 length
+//   u32 checkpoint count
+//   per checkpoint: u64 offset in the stream, u64 offset in the file in bits,
+//                   u32 length, the window before it (deflated)
+//
+// flate2 doesn't say where blocks start, so finding them takes the small inflater
+// below. It's only used for building the index and reading from checkpoints.
+
+const MAGIC: &[u8; 8] = b"PRINDEX3";
+const HASH_LEN: usize = 64;
+const WINDOW: usize = 32768;
+// roughly how much to inflate at most to get at an entry
+const SPAN: u64 = 1 << 20;
+
+// https://www.rfc-editor.org/rfc/rfc1951#section-3.2.5
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// order the code length code lengths come in
+const CL_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<R: Read> {
+    r: R,
+    buf: u64,
+    bits: u32,
+    // bits taken so far, counted from where reading started
+    pos: u64,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(r: R) -> Self {
+        BitReader {
+            r,
+            buf: 0,
+            bits: 0,
+            pos: 0,
+        }
+    }
+
+    // fills up to n bits, less only at the end of the file
+    fn fill(&mut self, n: u32) -> Result<(), Box<dyn error::Error>> {
+        while self.bits < n {
+            let mut b = [0u8];
+            if self.r.read(&mut b)? == 0 {
+                break;
+            }
+            self.buf |= (b[0] as u64) << self.bits;
+            self.bits += 8;
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, n: u32) -> Result<u32, Box<dyn error::Error>> {
+        if n == 0 {
+            return Ok(0);
+        }
+        self.fill(n)?;
+        if self.bits < n {
+            return Err("Deflate stream ended early".into());
+        }
+        let v = (self.buf & ((1 << n) - 1)) as u32;
+        self.buf >>= n;
+        self.bits -= n;
+        self.pos += n as u64;
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        let n = self.bits % 8;
+        self.buf >>= n;
+        self.bits -= n;
+        self.pos += n as u64;
+    }
+}
+
+// canonical huffman code as a table indexed by the next bits of the stream
+struct Huffman {
+    // symbol and code length, length 0 for bits that aren't a code
+    table: Vec<(u16, u8)>,
+    bits: u32,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, Box<dyn error::Error>> {
+        let bits = lengths.iter().copied().max().unwrap_or(0).max(1) as u32;
+        let mut count = [0u32; 16];
+        for x in lengths {
+            count[*x as usize] += 1;
+        }
+        count[0] = 0;
+        let mut next = [0u32; 16];
+        let mut code = 0;
+        for i in 1..16 {
+            code = (code + count[i - 1]) << 1;
+            next[i] = code;
+        }
+
+        let mut table = vec![(0u16, 0u8); 1 << bits];
+        for (sym, len) in lengths.iter().enumerate() {
+            let len = *len as u32;
+            if len == 0 {
+                continue;
+            }
+            let code = next[len as usize];
+            next[len as usize] += 1;
+            if code >= 1 << len {
+                return Err("Invalid huffman code lengths".into());
+            }
+            // the stream has codes starting from their highest bit
+            let rev = code.reverse_bits() >> (32 - len);
+            let mut i = rev as usize;
+            while i < table.len() {
+                table[i] = (sym as u16, len as u8);
+                i += 1 << len;
+            }
+        }
+        Ok(Huffman { table, bits })
+    }
+
+    fn decode<R: Read>(&self, br: &mut BitReader<R>) -> Result<u16, Box<dyn error::Error>> {
+        br.fill(self.bits)?;
+        let (sym, len) = self.table[(br.buf & ((1 << self.bits) - 1)) as usize];
+        if len == 0 || len as u32 > br.bits {
+            return Err("Invalid huffman code".into());
+        }
+        br.take(len as u32)?;
+        Ok(sym)
+    }
+}
+
+struct Inflater<R: Read> {
+    br: BitReader<R>,
+    // the window in front of what's new, then everything inflated since the caller
+    // last took it
+    out: Vec<u8>,
+    // stream offset of out[0]
+    base: u64,
+    done: bool,
+}
+
+impl<R: Read> Inflater<R> {
+    fn new(br: BitReader<R>, window: Vec<u8>, offset: u64) -> Self {
+        Inflater {
+            br,
+            base: offset - window.len() as u64,
+            out: window,
+            done: false,
+        }
+    }
+
+    fn offset(&self) -> u64 {
+        self.base + self.out.len() as u64
+    }
+
+    // the last 32KB, what a checkpoint here needs
+    fn window(&self) -> &[u8] {
+        &self.out[self.out.len().saturating_sub(WINDOW)..]
+    }
+
+    // drops everything but the window, returns what was dropped after `from`
+    fn take_until_window(&mut self, from: u64) -> Vec<u8> {
+        let keep = self.out.len().saturating_sub(WINDOW);
+        let start = (from.saturating_sub(self.base) as usize).min(keep);
+        let taken = self.out[start..keep].to_vec();
+        self.out.drain(..keep);
+        self.base += keep as u64;
+        taken
+    }
+
+    fn block(&mut self) -> Result<(), Box<dyn error::Error>> {
+        let last = self.br.take(1)? == 1;
+        match self.br.take(2)? {
+            0 => {
+                self.br.align();
+                let len = self.br.take(16)?;
+                let nlen = self.br.take(16)?;
+                if len != !nlen & 0xffff {
+                    return Err("Invalid stored block length".into());
+                }
+                for _ in 0..len {
+                    self.out.push(self.br.take(8)? as u8);
+                }
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let lit = Huffman::new(&lengths)?;
+                let dist = Huffman::new(&[5; 30])?;
+                self.codes(&lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = self.dynamic_codes()?;
+                self.codes(&lit, &dist)?;
+            }
+            _ => return Err("Invalid block type".into()),
+        }
+        self.done = last;
+        Ok(())
+    }
+
+    fn dynamic_codes(&mut self) -> Result<(Huffman, Huffman), Box<dyn error::Error>> {
+        let hlit = self.br.take(5)? as usize + 257;
+        let hdist = self.br.take(5)? as usize + 1;
+        let hclen = self.br.take(4)? as usize + 4;
+        let mut cl = [0u8; 19];
+        for x in CL_ORDER.iter().take(hclen) {
+            cl[*x] = self.br.take(3)? as u8;
+        }
+        let cl = Huffman::new(&cl)?;
+
+        let mut lengths = vec![];
+        while lengths.len() < hlit + hdist {
+            let (value, repeat) = match cl.decode(&mut self.br)? {
+                x @ 0..=15 => (x as u8, 1),
+                16 => {
+                    let prev = *lengths.last().ok_or("Repeat without a length")?;
+                    (prev, 3 + self.br.take(2)?)
+                }
+                17 => (0, 3 + self.br.take(3)?),
+                _ => (0, 11 + self.br.take(7)?),
+            };
+            for _ in 0..repeat {
+                lengths.push(value);
+            }
+        }
+        if lengths.len() != hlit + hdist {
+            return Err("Code lengths run past the end".into());
+        }
+        Ok((
+            Huffman::new(&lengths[..hlit])?,
+            Huffman::new(&lengths[hlit..])?,
+        ))
+    }
+
+    fn codes(&mut self, lit: &Huffman, dist: &Huffman) -> Result<(), Box<dyn error::Error>> {
+        loop {
+            let sym = lit.decode(&mut self.br)? as usize;
+            if sym < 256 {
+                self.out.push(sym as u8);
+                continue;
+            }
+            if sym == 256 {
+                return Ok(());
+            }
+            let i = sym - 257;
+            if i >= LEN_BASE.len() {
+                return Err("Invalid length code".into());
+            }
+            let len = LEN_BASE[i] as usize + self.br.take(LEN_EXTRA[i] as u32)? as usize;
+            let d = dist.decode(&mut self.br)? as usize;
+            if d >= DIST_BASE.len() {
+                return Err("Invalid distance code".into());
+            }
+            let d = DIST_BASE[d] as usize + self.br.take(DIST_EXTRA[d] as u32)? as usize;
+            if d > self.out.len() {
+                return Err("Distance goes back too far".into());
+            }
+            let start = self.out.len() - d;
+            for k in 0..len {
+                let b = self.out[start + k];
+                self.out.push(b);
+            }
+        }
+    }
+}
+
+struct IndexEntry {
+    entry: archive::Entry,
+    offset: u64,
+    len: u32,
+}
+
+struct Checkpoint {
+    offset: u64,
+    bits: u64,
+    window: Vec<u8>,
+}
+
+struct Index {
+    hash: String,
+    len: u64,
+    entries: Vec<IndexEntry>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+pub fn index_path(archive_path: &Path) -> PathBuf {
+    let mut name = archive_path.file_name().unwrap().to_owned();
+    name.push(".idx");
+    archive_path.with_file_name(name)
+}
+
+fn file_hash(path: &Path) -> Result<String, Box<dyn error::Error>> {
+    Ok(common::hash_hex_reader(&mut BufReader::new(
+        fs::File::open(path)?,
+    ))?)
+}
+
+// where every payload is, from the header and the lengths
+fn locate_entries(path: &Path) -> Result<(Vec<IndexEntry>, u64), Box<dyn error::Error>> {
+    let mut rdr = archive::ArchiveReader::open(path)?;
+    let mut offset = 4 + rdr
+        .entries
+        .iter()
+        .map(|x| 2 + common::encode_name(&x.name).len() as u64)
+        .sum::<u64>();
+    let mut entries = vec![];
+    while let Some((i, data)) = rdr.next_entry()? {
+        offset += 4;
+        entries.push(IndexEntry {
+            entry: rdr.entries[i].clone(),
+            offset,
+            len: data.len() as u32,
+        });
+        offset += data.len() as u64;
+    }
+    Ok((entries, offset))
+}
+
+// starts of blocks every SPAN bytes or so with the window in front of them, and
+// how much the whole stream inflates to
+fn find_checkpoints<R: Read>(r: R) -> Result<(Vec<Checkpoint>, u64), Box<dyn error::Error>> {
+    let mut inf = Inflater::new(BitReader::new(r), vec![], 0);
+    let mut checkpoints = vec![];
+    let mut last = 0;
+    while !inf.done {
+        if inf.offset() - last >= SPAN {
+            last = inf.offset();
+            checkpoints.push(Checkpoint {
+                offset: last,
+                bits: inf.br.pos,
+                window: inf.window().to_vec(),
+            });
+        }
+        inf.block()?;
+        inf.take_until_window(u64::MAX);
+    }
+    Ok((checkpoints, inf.offset()))
+}
+
+// picks the stream up at a checkpoint, or at the start without one
+fn resume<R: Read + Seek>(
+    mut r: R,
+    c: Option<&Checkpoint>,
+) -> Result<Inflater<R>, Box<dyn error::Error>> {
+    let (offset, bits, window) = match c {
+        Some(c) => (c.offset, c.bits, c.window.clone()),
+        None => (0, 0, vec![]),
+    };
+    r.seek(SeekFrom::Start(bits / 8))?;
+    let mut br = BitReader::new(r);
+    br.take((bits % 8) as u32)?;
+    Ok(Inflater::new(br, window, offset))
+}
+
+fn build(path: &Path) -> Result<Index, Box<dyn error::Error>> {
+    let hash = file_hash(path)?;
+    let (entries, len) = locate_entries(path)?;
+
+    let (checkpoints, inflated) = find_checkpoints(BufReader::new(fs::File::open(path)?))?;
+    if inflated != len {
+        return Err(format!(
+            "Inflated {} bytes but the entries add up to {}",
+            inflated, len
+        )
+        .into());
+    }
+
+    Ok(Index {
+        hash,
+        len,
+        entries,
+        checkpoints,
+    })
+}
+
+fn write_index(index: &Index) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    let mut w = vec![];
+    w.write_all(MAGIC)?;
+    w.write_all(index.hash.as_bytes())?;
+    w.write_u64::<LittleEndian>(index.len)?;
+    w.write_u32::<LittleEndian>(index.entries.len() as u32)?;
+    for x in &index.entries {
+        let name = common::encode_name(&x.entry.name);
+        w.write_u8(name.len() as u8)?;
+        w.write_all(&name)?;
+        w.write_u8(x.entry.type_)?;
+        w.write_u64::<LittleEndian>(x.offset)?;
+        w.write_u32::<LittleEndian>(x.len)?;
+    }
+    w.write_u32::<LittleEndian>(index.checkpoints.len() as u32)?;
+    for x in &index.checkpoints {
+        w.write_u64::<LittleEndian>(x.offset)?;
+        w.write_u64::<LittleEndian>(x.bits)?;
+        let mut comp = DeflateEncoder::new(vec![], Compression::best());
+        comp.write_all(&x.window)?;
+        let window = comp.finish()?;
+        w.write_u32::<LittleEndian>(window.len() as u32)?;
+        w.write_all(&window)?;
+    }
+    Ok(w)
+}
+
+fn read_index(data: &[u8]) -> Result<Index, Box<dyn error::Error>> {
+    let mut r = Cursor::new(data);
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err("Not an index".into());
+    }
+    let mut hash = [0u8; HASH_LEN];
+    r.read_exact(&mut hash)?;
+    let len = r.read_u64::<LittleEndian>()?;
+
+    let mut entries = vec![];
+    for _ in 0..r.read_u32::<LittleEndian>()? {
+        let name_len = r.read_u8()?;
+        let name = common::read_string(&mut r, name_len)?;
+        let type_ = r.read_u8()?;
+        entries.push(IndexEntry {
+            entry: archive::Entry { name, type_ },
+            offset: r.read_u64::<LittleEndian>()?,
+            len: r.read_u32::<LittleEndian>()?,
+        });
+    }
+    let mut checkpoints = vec![];
+    for _ in 0..r.read_u32::<LittleEndian>()? {
+        let offset = r.read_u64::<LittleEndian>()?;
+        let bits = r.read_u64::<LittleEndian>()?;
+        let mut comp = vec![0u8; r.read_u32::<LittleEndian>()? as usize];
+        r.read_exact(&mut comp)?;
+        let mut window = vec![];
+        DeflateDecoder::new(comp.as_slice()).read_to_end(&mut window)?;
+        checkpoints.push(Checkpoint {
+            offset,
+            bits,
+            window,
+        });
+    }
+    Ok(Index {
+        hash: String::from_utf8(hash.to_vec())?,
+        len,
+        entries,
+        checkpoints,
+    })
+}
+
+// builds <archive>.idx
+pub fn create(path: &Path) -> Result<(), Box<dyn error::Error>> {
+    let index = build(path)?;
+    let out_file = index_path(path);
+    println!(
+        "{} entries, {} checkpoints over {} bytes",
+        index.entries.len(),
+        index.checkpoints.len(),
+        index.len
+    );
+    println!("Writing index to {:?}...", out_file);
+    output::write_atomic(&out_file, &write_index(&index)?)?;
+    OPENED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .remove(&cache_key(path));
+    Ok(())
+}
+
+// an index that was checked against its archive, for reading entries through it
+pub struct Indexed {
+    path: PathBuf,
+    index: Index,
+}
+
+// indexes this run already opened (or found missing or stale) by archive, so every
+// archive is hashed once no matter how many entries are read from it
+static OPENED: Mutex<Option<HashMap<PathBuf, Option<Arc<Indexed>>>>> = Mutex::new(None);
+
+fn cache_key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+impl Indexed {
+    // None if there is no index or it was made for a different file, the caller has
+    // to go the long way then. the lock is held while hashing so parallel jobs on
+    // the same archive wait for the first one instead of hashing it again
+    pub fn open(path: &Path) -> Result<Option<Arc<Indexed>>, Box<dyn error::Error>> {
+        let mut opened = OPENED.lock().unwrap();
+        let opened = opened.get_or_insert_with(HashMap::new);
+        let key = cache_key(path);
+        if let Some(v) = opened.get(&key) {
+            return Ok(v.clone());
+        }
+        let v = Indexed::load(path)?.map(Arc::new);
+        opened.insert(key, v.clone());
+        Ok(v)
+    }
+
+    fn load(path: &Path) -> Result<Option<Indexed>, Box<dyn error::Error>> {
+        let data = match fs::read(index_path(path)) {
+            Ok(v) => v,
+            Err(_) => return Ok(None),
+        };
+        let index = match read_index(&data) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Warning: couldn't read {:?}: {}", index_path(path), e);
+                return Ok(None);
+            }
+        };
+        // the checkpoints point at bits of the compressed file, so it has to be the
+        // exact same file and not just the same entries
+        if index.hash != file_hash(path)? {
+            eprintln!(
+                "Warning: {:?} is out of date, run index again",
+                index_path(path)
+            );
+            return Ok(None);
+        }
+        Ok(Some(Indexed {
+            path: path.to_owned(),
+            index,
+        }))
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &archive::Entry> {
+        self.index.entries.iter().map(|x| &x.entry)
+    }
+
+    pub fn read(&self, name: &str) -> Result<archive::Loaded, Box<dyn error::Error>> {
+        let path = &self.path;
+        let x = self
+            .index
+            .entries
+            .iter()
+            .find(|x| x.entry.name == name)
+            .ok_or_else(|| format!("{:?} has no entry \"{}\"", path, name))?;
+
+        // the length in front of the payload is read too, as a check
+        let start = x.offset - 4;
+        let end = x.offset + x.len as u64;
+        let c = self
+            .index
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|c| c.offset <= start);
+        let mut inf = resume(BufReader::new(fs::File::open(path)?), c)?;
+
+        let mut buf = vec![];
+        while inf.offset() < end {
+            if inf.done {
+                return Err(format!("{:?} ended before \"{}\"", path, name).into());
+            }
+            inf.block()?;
+            buf.extend(inf.take_until_window(start));
+        }
+        let from = start.max(inf.base) - inf.base;
+        buf.extend_from_slice(&inf.out[from as usize..]);
+        buf.truncate((end - start) as usize);
+
+        let mut r = Cursor::new(&buf);
+        if r.read_i32::<LittleEndian>()? as u32 != x.len {
+            return Err(format!("{:?} doesn't match its archive", index_path(path)).into());
+        }
+        Ok((x.entry.clone(), buf.split_off(4)))
+    }
+}
+
+// reads an entry through the index next to the archive, None without a usable index
+pub fn read_entry(
+    path: &Path,
+    name: &str,
+) -> Result<Option<archive::Loaded>, Box<dyn error::Error>> {
+    match Indexed::open(path)? {
+        Some(v) => v.read(name).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // words with repeats, compresses like real data and isn't all the same
+    fn sample(len: usize) -> Vec<u8> {
+        let words: [&[u8]; 6] = [
+            b"pyongyang ",
+            b"racer ",
+            b"model ",
+            b"\x00\x01\x02",
+            b"map ",
+            b"\xff",
+        ];
+        let mut x = 0x2545f491u32;
+        let mut data = vec![];
+        while data.len() < len {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            data.extend_from_slice(words[x as usize % words.len()]);
+            if x & 15 == 0 {
+                data.push((x >> 8) as u8);
+            }
+        }
+        data.truncate(len);
+        data
+    }
+
+    fn compress(data: &[u8], level: Compression) -> Vec<u8> {
+        let mut comp = DeflateEncoder::new(vec![], level);
+        comp.write_all(data).unwrap();
+        comp.finish().unwrap()
+    }
+
+    fn block_type(comp: &[u8]) -> u8 {
+        (comp[0] >> 1) & 3
+    }
+
+    // everything from the checkpoint (or the start) to the end
+    fn inflate(comp: &[u8], c: Option<&Checkpoint>) -> Vec<u8> {
+        let mut inf = resume(Cursor::new(comp), c).unwrap();
+        let from = c.map(|x| x.offset).unwrap_or(0);
+        let mut data = vec![];
+        while !inf.done {
+            inf.block().unwrap();
+            data.extend(inf.take_until_window(from));
+        }
+        let start = from.saturating_sub(inf.base) as usize;
+        data.extend_from_slice(&inf.out[start..]);
+        data
+    }
+
+    fn flate2(comp: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        DeflateDecoder::new(comp).read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn stored_blocks() {
+        let data = sample(200_000);
+        let comp = compress(&data, Compression::none());
+        assert_eq!(block_type(&comp), 0);
+        assert_eq!(inflate(&comp, None), flate2(&comp));
+    }
+
+    #[test]
+    fn fixed_blocks() {
+        let data = b"racer racer racer map".to_vec();
+        let comp = compress(&data, Compression::fast());
+        assert_eq!(block_type(&comp), 1);
+        assert_eq!(inflate(&comp, None), flate2(&comp));
+    }
+
+    #[test]
+    fn dynamic_blocks() {
+        let data = sample(500_000);
+        let comp = compress(&data, Compression::best());
+        assert_eq!(block_type(&comp), 2);
+        assert_eq!(inflate(&comp, None), flate2(&comp));
+    }
+
+    #[test]
+    fn empty_stream() {
+        let comp = compress(&[], Compression::best());
+        assert!(inflate(&comp, None).is_empty());
+    }
+
+    #[test]
+    fn resume_from_checkpoints() {
+        let data = sample(3 * SPAN as usize + 1000);
+        for level in [Compression::none(), Compression::best()] {
+            let comp = compress(&data, level);
+            let (checkpoints, len) = find_checkpoints(Cursor::new(&comp)).unwrap();
+            assert_eq!(len, data.len() as u64);
+            assert!(checkpoints.len() >= 2);
+            for c in &checkpoints {
+                let offset = c.offset as usize;
+                assert_eq!(c.window, data[offset - WINDOW..offset]);
+                assert_eq!(inflate(&comp, Some(c)), data[offset..]);
+            }
+        }
+    }
+
+    #[test]
+    fn index_only_for_the_same_file() {
+        let dir = std::env::temp_dir().join(format!("seek_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let entries = |last: &[u8]| -> Vec<archive::Loaded> {
+            [
+                ("a.box", sample(SPAN as usize + 1000)),
+                ("b.png", last.to_vec()),
+            ]
+            .into_iter()
+            .map(|(name, data)| {
+                let entry = archive::Entry {
+                    name: name.to_owned(),
+                    type_: 0,
+                };
+                (entry, data)
+            })
+            .collect()
+        };
+        let path = dir.join("common.dat");
+        fs::write(&path, archive::write_all(&entries(b"first")).unwrap()).unwrap();
+        create(&path).unwrap();
+        let index = Indexed::open(&path).unwrap().unwrap();
+        assert_eq!(index.read("b.png").unwrap().1, b"first");
+        // the same one comes back without reading the index again
+        assert!(Arc::ptr_eq(&index, &Indexed::open(&path).unwrap().unwrap()));
+
+        // a different archive with that index next to it
+        let other = dir.join("other.dat");
+        fs::write(&other, archive::write_all(&entries(b"second")).unwrap()).unwrap();
+        fs::copy(index_path(&path), index_path(&other)).unwrap();
+        assert!(Indexed::open(&other).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
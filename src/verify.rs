@@ -0,0 +1,184 @@
+use std::{
+    error, fs,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::bufread::DeflateDecoder;
+
+use crate::{
+    archive::{self, Entry},
+    common, output, registry,
+};
+
+// Goes through an archive without trusting anything in it. Everything that can be
+// inflated is, and the layout is checked against what's actually there instead of
+// reading until something fails. Entries in front of the first broken spot are
+// complete and can be written to a new archive.
+
+// everything that could be inflated, and why it stopped if it wasn't the end
+fn inflate(file: &[u8]) -> (Vec<u8>, Option<String>, usize) {
+    let mut rdr = DeflateDecoder::new(file);
+    let mut data = vec![];
+    let mut buf = vec![0u8; 1 << 16];
+    let error = loop {
+        match rdr.read(&mut buf) {
+            Ok(0) => break None,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) => break Some(e.to_string()),
+        }
+    };
+    let used = rdr.total_in() as usize;
+    (data, error, used)
+}
+
+pub fn verify(
+    path: &Path,
+    salvage: bool,
+    output: Option<String>,
+) -> Result<(), Box<dyn error::Error>> {
+    let out_file = match output {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(format!(
+            "{}_salvaged.dat",
+            path.file_stem().unwrap().to_str().unwrap()
+        )),
+    };
+    if salvage && out_file.try_exists()? {
+        return Err(format!("Output file {:?} already exists", out_file).into());
+    }
+
+    let file = fs::read(path)?;
+    let (data, error, used) = inflate(&file);
+    let mut problems = vec![];
+    match error {
+        Some(e) => problems.push(format!(
+            "Deflate stream breaks off after {} bytes: {}",
+            data.len(),
+            e
+        )),
+        None if used < file.len() => problems.push(format!(
+            "{} bytes in the file after the end of the deflate stream",
+            file.len() - used
+        )),
+        None => (),
+    }
+
+    let len = data.len() as u64;
+    let mut r = Cursor::new(data.as_slice());
+    let left = |r: &Cursor<&[u8]>| len - r.position();
+
+    // the header, every entry needs at least its name length and type
+    let count = match r.read_i32::<LittleEndian>() {
+        Ok(v) if v < 0 => {
+            problems.push(format!("Entry count is {}", v));
+            None
+        }
+        Ok(v) => Some(v as usize),
+        Err(_) => {
+            problems.push(String::from("Stream ends before the entry count"));
+            None
+        }
+    };
+    if let Some(n) = count {
+        if n as u64 * 2 > left(&r) {
+            problems.push(format!(
+                "Entry count is {}, but only {} bytes are left for the names",
+                n,
+                left(&r)
+            ));
+        }
+    }
+    let mut entries = vec![];
+    let n = count.unwrap_or(0);
+    for i in 0..n {
+        let name_len = match r.read_u8() {
+            Ok(v) => v,
+            Err(_) => {
+                problems.push(format!("Header ends at entry {} of {}", i, n));
+                break;
+            }
+        };
+        if name_len == 0 {
+            problems.push(format!("Entry {} has an empty name", i));
+        }
+        if name_len as u64 + 1 > left(&r) {
+            problems.push(format!(
+                "Name of entry {} is {} bytes, but the header ends after {}",
+                i,
+                name_len,
+                left(&r)
+            ));
+            break;
+        }
+        let name = common::read_string(&mut r, name_len)?;
+        let type_ = r.read_u8()?;
+        entries.push(Entry { name, type_ });
+    }
+
+    // payloads, only those that are there completely are kept
+    let mut complete = vec![];
+    if Some(entries.len()) == count {
+        for entry in &entries {
+            let payload_len = match r.read_i32::<LittleEndian>() {
+                Ok(v) => v,
+                Err(_) => {
+                    problems.push(format!("Stream ends before \"{}\"", entry.name));
+                    break;
+                }
+            };
+            if payload_len < 0 || payload_len as u64 > left(&r) {
+                problems.push(format!(
+                    "\"{}\" is {} bytes, but only {} are left",
+                    entry.name,
+                    payload_len,
+                    left(&r)
+                ));
+                break;
+            }
+            let start = r.position() as usize;
+            let payload = data[start..start + payload_len as usize].to_vec();
+            r.set_position((start + payload.len()) as u64);
+
+            // the data is all there even if it doesn't parse, so it still counts
+            if let Some(Err(e)) = registry::by_type(entry.type_).map(|x| x.parse(&payload)) {
+                problems.push(format!("\"{}\" doesn't parse: {}", entry.name, e));
+            }
+            complete.push((entry.clone(), payload));
+        }
+        if complete.len() == entries.len() && left(&r) > 0 {
+            problems.push(format!(
+                "{} bytes in the stream after the last entry",
+                left(&r)
+            ));
+        }
+    }
+
+    for x in &problems {
+        println!("{}", x);
+    }
+    if problems.is_empty() {
+        println!("{} entries, no problems found", complete.len());
+        return Ok(());
+    }
+    println!(
+        "{} problems, {} of {} entries are complete",
+        problems.len(),
+        complete.len(),
+        n
+    );
+
+    if !salvage {
+        if !complete.is_empty() {
+            println!("Use --salvage to write them to a new archive");
+        }
+        return Ok(());
+    }
+    if complete.is_empty() {
+        return Err("Nothing to salvage".into());
+    }
+    println!("Writing {} entries to {:?}...", complete.len(), out_file);
+    output::write_atomic(&out_file, &archive::write_all(&complete)?)?;
+    Ok(())
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::HashSet,
+    error, fs,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    archive::{self, Entry},
+    output, registry,
+};
+
+// Archives as zip files for tools that don't know our container. Every entry is a
+// member named like the entry, and archive.toml in the zip has what zip can't hold:
+// the type ids and the order. Names that can't be a member (outside the zip,
+// duplicates) go in under a made up name, the manifest has the real one.
+//
+// Zips without a manifest work too, everything in them is packed like a folder.
+
+pub const MANIFEST: &str = "archive.toml";
+// members for names that can't be one themselves
+const OTHER: &str = "_entries";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ZipEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: u8,
+    // member in the zip
+    pub file: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    // archive::content_hash of the archive the zip was made from
+    pub content_hash: String,
+    pub entries: Vec<ZipEntry>,
+}
+
+pub fn dat2zip(input: &Path, output: Option<String>) -> Result<(), Box<dyn error::Error>> {
+    let out_file = match output {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(format!(
+            "{}.zip",
+            input.file_stem().unwrap().to_str().unwrap()
+        )),
+    };
+    if out_file.try_exists()? {
+        return Err(format!("Output file {:?} already exists", out_file).into());
+    }
+
+    let content_hash = archive::content_hash(input)?;
+    let mut rdr = archive::ArchiveReader::open(input)?;
+    let checks = archive::check_names(&rdr.entries);
+
+    // the manifest can't be taken by an entry
+    let mut used = HashSet::from([MANIFEST.to_owned()]);
+    let mut entries = vec![];
+    for (i, (x, check)) in rdr.entries.iter().zip(&checks).enumerate() {
+        let file = match &check.path {
+            Ok(v) => v.to_str().unwrap().replace('\\', "/"),
+            Err(e) => {
+                eprintln!("\"{}\" {}, it goes in {}/{}", x.name, e, OTHER, i);
+                format!("{}/{}", OTHER, i)
+            }
+        };
+        // a made up name can still be the real name of another entry
+        let file = if used.contains(&file) {
+            format!("{}/{}", OTHER, i)
+        } else {
+            file
+        };
+        if !used.insert(file.clone()) {
+            return Err(format!("Can't find a member name for \"{}\"", x.name).into());
+        }
+        entries.push(ZipEntry {
+            name: x.name.clone(),
+            type_: x.type_,
+            file,
+        });
+    }
+    let manifest = Manifest {
+        content_hash,
+        entries,
+    };
+
+    println!(
+        "Writing {} entries to {:?}...",
+        manifest.entries.len(),
+        out_file
+    );
+    output::write_atomic_with(&out_file, |f| {
+        let mut zip = ZipWriter::new(BufWriter::new(f));
+        let opts = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file(MANIFEST, opts)?;
+        zip.write_all(toml::to_string_pretty(&manifest)?.as_bytes())?;
+        while let Some((i, data)) = rdr.next_entry()? {
+            zip.start_file(manifest.entries[i].file.as_str(), opts)?;
+            zip.write_all(&data)?;
+        }
+        zip.finish()?.into_inner().map_err(|e| e.into_error())?;
+        Ok(())
+    })
+}
+
+pub fn zip2dat(input: &Path, output: Option<String>) -> Result<(), Box<dyn error::Error>> {
+    let out_file = match output {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(format!(
+            "{}.dat",
+            input.file_stem().unwrap().to_str().unwrap()
+        )),
+    };
+    if out_file.try_exists()? {
+        return Err(format!("Output file {:?} already exists", out_file).into());
+    }
+
+    let mut zip = ZipArchive::new(fs::File::open(input)?)?;
+    let manifest: Option<Manifest> = match zip.by_name(MANIFEST) {
+        Ok(mut v) => {
+            let mut text = String::new();
+            v.read_to_string(&mut text)?;
+            let m = toml::from_str(&text)
+                .map_err(|e| format!("Couldn't parse {} in {:?}: {}", MANIFEST, input, e))?;
+            Some(m)
+        }
+        Err(_) => None,
+    };
+
+    // what the manifest has keeps its type and place
+    let mut plan: Vec<(Entry, String)> = vec![];
+    let mut used = HashSet::from([MANIFEST.to_owned()]);
+    if let Some(m) = &manifest {
+        for x in &m.entries {
+            if zip.index_for_name(&x.file).is_none() {
+                return Err(
+                    format!("{:?} has no {:?}, it's in {}", input, x.file, MANIFEST).into(),
+                );
+            }
+            used.insert(x.file.clone());
+            let entry = Entry {
+                name: x.name.clone(),
+                type_: x.type_,
+            };
+            plan.push((entry, x.file.clone()));
+        }
+    }
+
+    // everything else was added to the zip, the type goes by the name like in pack
+    let mut added: Vec<(Entry, String)> = vec![];
+    for name in zip.file_names() {
+        if used.contains(name) || name.ends_with('/') {
+            continue;
+        }
+        match registry::by_name(name) {
+            Some(v) => added.push((
+                Entry {
+                    name: name.to_owned(),
+                    type_: v.type_id(),
+                },
+                name.to_owned(),
+            )),
+            None => eprintln!("Skipping unsupported file {:?}", name),
+        }
+    }
+    added.sort_by(|a, b| {
+        b.0.type_
+            .cmp(&a.0.type_)
+            .then_with(|| a.0.name.cmp(&b.0.name))
+    });
+    for x in added {
+        println!(
+            "Adding \"{}\", type: {} ({})",
+            x.0.name,
+            x.0.type_,
+            registry::type_name(x.0.type_)
+        );
+        let pos = archive::insert_position(plan.iter().map(|v| &v.0), x.0.type_);
+        plan.insert(pos, x);
+    }
+    if plan.is_empty() {
+        return Err(format!("{:?} has nothing to pack", input).into());
+    }
+
+    let entries: Vec<Entry> = plan.iter().map(|x| x.0.clone()).collect();
+    println!("Writing {} entries to {:?}...", entries.len(), out_file);
+    output::write_atomic_with(&out_file, |f| {
+        let mut w = archive::ArchiveWriter::new(BufWriter::new(f), &entries)?;
+        let mut data = vec![];
+        for (_, file) in &plan {
+            data.clear();
+            zip.by_name(file)?.read_to_end(&mut data)?;
+            w.add(&data)?;
+        }
+        w.finish()?.into_inner().map_err(|e| e.into_error())?;
+        Ok(())
+    })?;
+
+    let hash = archive::content_hash(&out_file)?;
+    println!("Content hash: {}", hash);
+    if let Some(m) = &manifest {
+        if m.content_hash == hash {
+            println!("Same content as the archive the zip was made from");
+        } else {
+            println!("Content differs from the archive the zip was made from");
+        }
+    }
+    Ok(())
+}